@@ -0,0 +1,126 @@
+use crate::color::Color;
+use crate::direction::Direction;
+use crate::point::Point;
+
+/// How an animated value moves between its keyframes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interpolation {
+    /// Holds each keyframe's value until the next one.
+    Step,
+    Linear,
+    /// Eases in and out of every keyframe.
+    Smooth,
+    /// A Catmull-Rom spline through the keyframes.
+    Spline,
+}
+
+/// A value that can be animated, by blending up to four keyframe values with the given weights.
+pub trait Animatable: Copy {
+    fn blend(values: [Self; 4], weights: [f64; 4]) -> Self;
+}
+
+impl Animatable for f64 {
+    fn blend(values: [f64; 4], weights: [f64; 4]) -> f64 {
+        values.iter().zip(weights.iter()).map(|(v, w)| v * w).sum()
+    }
+}
+
+impl Animatable for Point {
+    fn blend(values: [Point; 4], weights: [f64; 4]) -> Point {
+        Point::new(
+            f64::blend(values.map(|v| v.x), weights),
+            f64::blend(values.map(|v| v.y), weights),
+            f64::blend(values.map(|v| v.z), weights),
+        )
+    }
+}
+
+impl Animatable for Direction {
+    fn blend(values: [Direction; 4], weights: [f64; 4]) -> Direction {
+        Direction::new(
+            f64::blend(values.map(|v| v.x), weights),
+            f64::blend(values.map(|v| v.y), weights),
+            f64::blend(values.map(|v| v.z), weights),
+        )
+    }
+}
+
+impl Animatable for Color {
+    fn blend(values: [Color; 4], weights: [f64; 4]) -> Color {
+        Color::new(
+            f64::blend(values.map(|v| v.r), weights),
+            f64::blend(values.map(|v| v.g), weights),
+            f64::blend(values.map(|v| v.b), weights),
+        )
+    }
+}
+
+/// Value of the animation at the given frame, which holds the first and last keyframe values outside
+/// of their range. `keyframes` must not be empty.
+pub fn evaluate<T: Animatable>(keyframes: &[(f64, T)], interpolation: Interpolation, frame: f64) -> T {
+    let mut keyframes = keyframes.to_vec();
+    keyframes.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let i = keyframes.partition_point(|(f, _)| *f <= frame);
+    if i == 0 {
+        return keyframes[0].1;
+    }
+    if i == keyframes.len() {
+        return keyframes[i - 1].1;
+    }
+
+    let (f1, v1) = keyframes[i - 1];
+    let (f2, v2) = keyframes[i];
+    let t = (frame - f1) / (f2 - f1);
+    let (v0, v3) = (
+        keyframes[i.saturating_sub(2)].1,
+        keyframes[(i + 1).min(keyframes.len() - 1)].1,
+    );
+    let weights = match interpolation {
+        Interpolation::Step => [0.0, 1.0, 0.0, 0.0],
+        Interpolation::Linear => [0.0, 1.0 - t, t, 0.0],
+        Interpolation::Smooth => {
+            let s = t * t * (3.0 - 2.0 * t);
+            [0.0, 1.0 - s, s, 0.0]
+        }
+        Interpolation::Spline => {
+            let (t2, t3) = (t * t, t * t * t);
+            [
+                0.5 * (-t + 2.0 * t2 - t3),
+                0.5 * (2.0 - 5.0 * t2 + 3.0 * t3),
+                0.5 * (t + 4.0 * t2 - 3.0 * t3),
+                0.5 * (-t2 + t3),
+            ]
+        }
+    };
+    T::blend([v0, v1, v2, v3], weights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    const KEYS: [(f64, f64); 3] = [(0.0, 0.0), (10.0, 10.0), (20.0, 0.0)];
+
+    #[test]
+    pub fn holds_outside_keyframes() {
+        assert_approx_eq!(evaluate(&KEYS, Interpolation::Linear, -5.0), 0.0);
+        assert_approx_eq!(evaluate(&KEYS, Interpolation::Linear, 25.0), 0.0);
+    }
+
+    #[test]
+    pub fn interpolation_modes() {
+        assert_approx_eq!(evaluate(&KEYS, Interpolation::Step, 5.0), 0.0);
+        assert_approx_eq!(evaluate(&KEYS, Interpolation::Linear, 5.0), 5.0);
+        assert_approx_eq!(evaluate(&KEYS, Interpolation::Smooth, 2.5), 1.5625);
+        assert_approx_eq!(evaluate(&KEYS, Interpolation::Spline, 10.0), 10.0);
+        assert!(evaluate(&KEYS, Interpolation::Spline, 5.0) > 5.0);
+    }
+
+    #[test]
+    pub fn animates_points() {
+        let keys = [(0.0, Point::zero()), (2.0, Point::new(2.0, 4.0, -2.0))];
+        assert_approx_eq!(evaluate(&keys, Interpolation::Linear, 1.0), Point::new(1.0, 2.0, -1.0));
+    }
+}
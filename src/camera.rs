@@ -0,0 +1,750 @@
+use std::f64;
+use std::fmt;
+
+use image::{DynamicImage, GenericImage, Pixel};
+
+use rand::Rng;
+
+use crate::direction::{Direction, Dot};
+use crate::lens::LensSystem;
+use crate::matrix::Matrix44f;
+use crate::point::Point;
+use crate::system::Ray;
+
+/// The shape of the opening in the lens, which gives out-of-focus highlights their shape.
+#[derive(Clone)]
+pub enum ApertureShape {
+    Circle,
+    /// A regular polygon with the given number of blades, rotated by the given angle in degrees.
+    Polygon(u32, f64),
+    /// A greyscale mask over the square around the lens, clipped to the circle inscribed in it;
+    /// brighter pixels let more light through.
+    Image(ApertureMask),
+}
+
+impl fmt::Debug for ApertureShape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApertureShape::Circle => f.write_str("ApertureShape::Circle"),
+            ApertureShape::Polygon(blades, rotation) => f
+                .debug_tuple("ApertureShape::Polygon")
+                .field(blades)
+                .field(rotation)
+                .finish(),
+            ApertureShape::Image(m) => f
+                .debug_struct("ApertureShape::Image")
+                .field("width", &m.width)
+                .field("height", &m.height)
+                .finish(),
+        }
+    }
+}
+
+impl ApertureShape {
+    /// Samples a point within the aperture, scaled to fit the unit disk.
+    fn sample(&self) -> (f64, f64) {
+        match self {
+            ApertureShape::Circle => uniform_disk_distribution(),
+            ApertureShape::Polygon(blades, rotation) => uniform_polygon_distribution(*blades, *rotation),
+            ApertureShape::Image(mask) => mask.sample(),
+        }
+    }
+}
+
+/// A thin lens in front of the camera, which blurs everything that isn't at the focus distance.
+#[derive(Debug, Clone)]
+pub struct ThinLens {
+    lens_radius: f64,
+    focus_distance: f64,
+    shape: ApertureShape,
+}
+
+impl ThinLens {
+    pub fn new(aperture: f64, focus_distance: f64, shape: ApertureShape) -> ThinLens {
+        ThinLens {
+            lens_radius: aperture * 0.5,
+            focus_distance,
+            shape,
+        }
+    }
+
+    /// Samples a point on the lens, in camera space.
+    fn sample(&self) -> Point {
+        let (x, y) = self.shape.sample();
+        Point::new(x * self.lens_radius, y * self.lens_radius, 0.0)
+    }
+}
+
+/// Uniformly distributed point on the unit disk, using Shirley's concentric mapping.
+pub fn uniform_disk_distribution() -> (f64, f64) {
+    let mut rng = rand::rng();
+    let a = 2.0 * rng.random::<f64>() - 1.0;
+    let b = 2.0 * rng.random::<f64>() - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, f64::consts::FRAC_PI_4 * (b / a))
+    } else {
+        (b, f64::consts::FRAC_PI_2 - f64::consts::FRAC_PI_4 * (a / b))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+/// Uniformly distributed point in a regular polygon inscribed in the unit circle.
+pub fn uniform_polygon_distribution(blades: u32, rotation: f64) -> (f64, f64) {
+    let mut rng = rand::rng();
+    // pick one of the triangles between the centre and an edge, then a point within it
+    let wedge = 2.0 * f64::consts::PI / blades as f64;
+    let start = rotation.to_radians() + wedge * rng.random_range(0..blades) as f64;
+    let (a, b) = (start.sin_cos(), (start + wedge).sin_cos());
+    let (mut u, mut v) = (rng.random::<f64>(), rng.random::<f64>());
+    if u + v > 1.0 {
+        u = 1.0 - u;
+        v = 1.0 - v;
+    }
+    (u * a.1 + v * b.1, u * a.0 + v * b.0)
+}
+
+/// Subdivisions along each side of a mask pixel when measuring how much of it is inside the unit disk.
+const MASK_COVERAGE_STEPS: u32 = 4;
+
+/// An aperture mask with the distribution of its brightness worked out when it's loaded, as the
+/// cumulative brightness of its rows and of the pixels along each row.
+#[derive(Clone)]
+pub struct ApertureMask {
+    width: u32,
+    height: u32,
+    rows: Vec<f64>,
+    columns: Vec<Vec<f64>>,
+}
+
+impl ApertureMask {
+    /// Creates a mask from the image stretched over the square around the lens, keeping the part
+    /// within the inscribed circle. Returns `None` if no light gets through that part.
+    pub fn new(image: &DynamicImage) -> Option<ApertureMask> {
+        let (width, height) = image.dimensions();
+        let columns: Vec<Vec<f64>> = (0..height)
+            .map(|py| {
+                let mut total = 0.0;
+                (0..width)
+                    .map(|px| {
+                        let luma = image.get_pixel(px, py).to_luma().channels()[0] as f64 / 255.0;
+                        total += luma * disk_coverage(px, py, width, height);
+                        total
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut total = 0.0;
+        let mut rows: Vec<f64> = columns
+            .iter()
+            .map(|c| {
+                total += c[c.len() - 1];
+                total
+            })
+            .collect();
+        if total == 0.0 {
+            return None;
+        }
+        rows.iter_mut().for_each(|c| *c /= total);
+        let columns = columns
+            .into_iter()
+            .map(|mut c| {
+                let row_total = c[c.len() - 1];
+                if row_total > 0.0 {
+                    c.iter_mut().for_each(|v| *v /= row_total);
+                }
+                c
+            })
+            .collect();
+        Some(ApertureMask {
+            width,
+            height,
+            rows,
+            columns,
+        })
+    }
+
+    /// Point distributed according to the brightness of the mask, picking a row and then a pixel
+    /// along it, and then a point in the part of the pixel within the unit disk.
+    fn sample(&self) -> (f64, f64) {
+        let mut rng = rand::rng();
+        let py = pick(&self.rows, rng.random::<f64>());
+        let px = pick(&self.columns[py], rng.random::<f64>());
+        loop {
+            let x = (px as f64 + rng.random::<f64>()) / self.width as f64;
+            let y = (py as f64 + rng.random::<f64>()) / self.height as f64;
+            // image rows run downwards, lens y runs upwards
+            let (x, y) = (2.0 * x - 1.0, 1.0 - 2.0 * y);
+            if x * x + y * y <= 1.0 {
+                return (x, y);
+            }
+        }
+    }
+}
+
+/// Index of the first entry of a cumulative distribution above `u`, skipping entries of zero weight.
+fn pick(cdf: &[f64], u: f64) -> usize {
+    cdf.partition_point(|&c| c <= u).min(cdf.len() - 1)
+}
+
+/// Fraction of a pixel of an image stretched over [-1, 1]² that lies within the unit disk.
+fn disk_coverage(px: u32, py: u32, width: u32, height: u32) -> f64 {
+    let n = MASK_COVERAGE_STEPS;
+    let inside = (0..n * n)
+        .filter(|i| {
+            let x = 2.0 * (px as f64 + (i % n) as f64 / n as f64 + 0.5 / n as f64) / width as f64 - 1.0;
+            let y = 2.0 * (py as f64 + (i / n) as f64 / n as f64 + 0.5 / n as f64) / height as f64 - 1.0;
+            x * x + y * y <= 1.0
+        })
+        .count();
+    inside as f64 / (n * n) as f64
+}
+
+/// How the camera maps image positions to rays.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Projection {
+    /// A pinhole camera with the given vertical field of view in degrees.
+    Perspective(f64),
+    /// Parallel rays covering a view of the given width in world units.
+    Orthographic(f64),
+    /// Longitude across the image and latitude down it, covering the whole sphere.
+    Equirectangular,
+    /// The six faces of a cube around the camera, laid out in two rows of three: right, left and up
+    /// above down, forward and back.
+    Cubemap,
+    /// A circular fisheye image filling the shorter side of the frame, with the given field of view
+    /// in degrees across the circle.
+    Fisheye(FisheyeMapping, f64),
+}
+
+/// How the angle from the view axis maps to the distance from the centre of a fisheye image.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FisheyeMapping {
+    /// Distance proportional to the angle.
+    Equidistant,
+    /// Distance proportional to the sine of half the angle, preserving solid angle.
+    Equisolid,
+}
+
+impl Projection {
+    /// Whether the projection covers more than can be focused through a thin lens.
+    pub fn is_panoramic(&self) -> bool {
+        !matches!(self, Projection::Perspective(_) | Projection::Orthographic(_))
+    }
+}
+
+/// Direction through a cube face, given the face index and a position on it in [-1, 1].
+fn cubemap_direction(face: u32, u: f64, v: f64) -> Direction {
+    match face {
+        0 => Direction::new(1.0, v, u),
+        1 => Direction::new(-1.0, v, -u),
+        2 => Direction::new(u, 1.0, v),
+        3 => Direction::new(u, -1.0, -v),
+        4 => Direction::new(u, v, -1.0),
+        _ => Direction::new(-u, v, 1.0),
+    }
+}
+
+/// How the two eyes of a stereo camera share the image.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StereoLayout {
+    /// Left eye in the left half, right eye in the right half.
+    SideBySide,
+    /// Left eye in the top half, right eye in the bottom half.
+    OverUnder,
+}
+
+/// A pair of eyes either side of the camera origin, both looking along the view direction.
+#[derive(Debug, Copy, Clone)]
+pub struct Stereo {
+    pub layout: StereoLayout,
+    /// Distance between the eyes.
+    pub interocular: f64,
+    /// Distance at which the eyes' views line up, so objects there appear at the depth of the
+    /// screen. Ignored by equirectangular projections, which converge at infinity.
+    pub convergence: f64,
+}
+
+impl Stereo {
+    /// Which eye sees the given image position (-1 for left, 1 for right), along with the position
+    /// and size of that eye's part of the image.
+    fn eye(&self, x: f64, y: f64, width: f64, height: f64) -> (f64, f64, f64, f64, f64) {
+        match self.layout {
+            StereoLayout::SideBySide => {
+                let half = width * 0.5;
+                if x < half {
+                    (-1.0, x, y, half, height)
+                } else {
+                    (1.0, x - half, y, half, height)
+                }
+            }
+            StereoLayout::OverUnder => {
+                let half = height * 0.5;
+                if y < half {
+                    (-1.0, x, y, width, half)
+                } else {
+                    (1.0, x, y - half, width, half)
+                }
+            }
+        }
+    }
+}
+
+/// The interval the camera's shutter is open for. Each ray is sent at a random moment within it.
+#[derive(Debug, Copy, Clone)]
+pub struct Shutter {
+    pub open: f64,
+    pub close: f64,
+}
+
+impl Shutter {
+    fn sample(&self) -> f64 {
+        self.open + rand::rng().random::<f64>() * (self.close - self.open)
+    }
+}
+
+/// Photographic exposure settings, which treat light intensities as luminance in cd/m².
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Exposure {
+    pub iso: f64,
+    /// Time the shutter is open for, in seconds.
+    pub shutter_speed: f64,
+    pub f_number: f64,
+}
+
+impl Default for Exposure {
+    fn default() -> Exposure {
+        Exposure {
+            iso: 100.0,
+            shutter_speed: 1.0 / 125.0,
+            f_number: 8.0,
+        }
+    }
+}
+
+impl Exposure {
+    /// Multiplier mapping scene luminance to film values, where 1.0 is the brightest luminance the
+    /// film records without clipping (using the saturation-based ISO speed of ISO 12232).
+    pub fn multiplier(&self) -> f64 {
+        let max_luminance = 78.0 / (0.65 * self.iso) * self.f_number * self.f_number / self.shutter_speed;
+        1.0 / max_luminance
+    }
+
+    /// Diameter of the opening of a lens with the given focal length.
+    pub fn aperture(&self, focal_length: f64) -> f64 {
+        focal_length / self.f_number
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Camera {
+    width: f64,
+    height: f64,
+    projection: Projection,
+    camera_to_world: Matrix44f,
+    pub lens: Option<ThinLens>,
+    pub stereo: Option<Stereo>,
+    pub shutter: Option<Shutter>,
+    /// Lens elements to trace rays through instead of the projection.
+    pub lens_system: Option<LensSystem>,
+    /// Multiplier applied to the light reaching the film.
+    pub exposure: f64,
+}
+
+/// Transform placing a camera at `origin` looking towards `look_at`, with `up` pointing as close to
+/// the top of the image as possible. If `up` is parallel to the view direction, the world axis
+/// furthest from it is used instead.
+pub fn look_at_transform(origin: Point, look_at: Point, up: Direction) -> Matrix44f {
+    let zaxis = (origin - look_at).normalize();
+    let mut xaxis = up.cross(zaxis);
+    if xaxis.length() < 1e-6 * up.length() {
+        let fallback = [
+            Direction::new(1.0, 0.0, 0.0),
+            Direction::new(0.0, 1.0, 0.0),
+            Direction::new(0.0, 0.0, 1.0),
+        ]
+        .into_iter()
+        .min_by(|a, b| a.dot(zaxis).abs().total_cmp(&b.dot(zaxis).abs()))
+        .unwrap();
+        xaxis = fallback.cross(zaxis);
+    }
+    let xaxis = xaxis.normalize();
+    let yaxis = zaxis.cross(xaxis);
+    Matrix44f([
+        [xaxis.x, xaxis.y, xaxis.z, 0.0],
+        [yaxis.x, yaxis.y, yaxis.z, 0.0],
+        [zaxis.x, zaxis.y, zaxis.z, 0.0],
+        [origin.x, origin.y, origin.z, 1.0],
+    ])
+}
+
+impl Camera {
+    /// Creates a camera looking down -z with +y up in camera space, placed in the world by
+    /// `camera_to_world`.
+    pub fn new(width: f64, height: f64, projection: Projection, camera_to_world: Matrix44f) -> Camera {
+        Camera {
+            width,
+            height,
+            projection,
+            camera_to_world,
+            lens: None,
+            stereo: None,
+            shutter: None,
+            lens_system: None,
+            exposure: 1.0,
+        }
+    }
+
+    pub fn origin(&self) -> Point {
+        Point::zero() * self.camera_to_world
+    }
+
+    /// Unit direction the camera looks in.
+    pub fn view_direction(&self) -> Direction {
+        (Direction::new(0.0, 0.0, -1.0) * self.camera_to_world).normalize()
+    }
+
+    /// Ray through the given position in the image, or `None` if the projection doesn't cover it.
+    pub fn pixel_ray(&self, x: f64, y: f64) -> Option<Ray> {
+        let (origin, dir_point) = match &self.lens_system {
+            Some(lens) => {
+                // the lens flips the image on the film
+                let film_width = lens.film_width();
+                let film_point = Point::new(
+                    (1.0 - 2.0 * x / self.width) * film_width * 0.5,
+                    (2.0 * y / self.height - 1.0) * film_width * 0.5 * self.height / self.width,
+                    0.0,
+                );
+                let (origin, direction) = lens.ray(film_point)?;
+                (origin, origin + direction)
+            }
+            None => self.pinhole_ray(x, y)?,
+        };
+        let origin = origin * self.camera_to_world;
+        let dir_point = dir_point * self.camera_to_world;
+        let mut ray = Ray::primary(origin, (dir_point - origin).normalize(), 0);
+        ray.time = self.shutter.map_or(0.0, |s| s.sample());
+        Some(ray)
+    }
+
+    /// Camera space origin and a second point along a ray through the given image position, for the
+    /// projection, stereo eye and thin lens.
+    fn pinhole_ray(&self, x: f64, y: f64) -> Option<(Point, Point)> {
+        let (origin, direction) = match &self.stereo {
+            Some(stereo) => {
+                let (eye, x, y, width, height) = stereo.eye(x, y, self.width, self.height);
+                let (origin, direction) = self.project(x, y, width, height)?;
+                let offset = eye * stereo.interocular * 0.5;
+                if self.projection == Projection::Equirectangular {
+                    // omni-directional stereo: the eyes sit on a circle, each ray leaving it at a
+                    // tangent; the offset shrinks towards the poles to avoid swapping the eyes
+                    (
+                        origin + Direction::new(-direction.z, 0.0, direction.x) * offset,
+                        direction,
+                    )
+                } else {
+                    // off-axis: the eyes' views meet on the plane of convergence
+                    let convergence_point = origin + direction * (stereo.convergence / -direction.z);
+                    let origin = origin + Direction::new(offset, 0.0, 0.0);
+                    (origin, convergence_point - origin)
+                }
+            }
+            None => self.project(x, y, self.width, self.height)?,
+        };
+        let lens = self.lens.as_ref().filter(|_| !self.projection.is_panoramic());
+        let (origin, dir_point) = match lens {
+            Some(lens) => {
+                // every ray through the lens converges on the plane of focus
+                let focus_point = origin + direction * (lens.focus_distance / -direction.z);
+                (origin + lens.sample(), focus_point)
+            }
+            None => (origin, origin + direction),
+        };
+        Some((origin, dir_point))
+    }
+
+    /// Camera space origin and direction through a position in an image of the given size.
+    fn project(&self, x: f64, y: f64, width: f64, height: f64) -> Option<(Point, Direction)> {
+        let aspect_ratio = width / height;
+        let ndcx = x / width;
+        let ndcy = y / height;
+        let ray = match self.projection {
+            Projection::Perspective(fov) => {
+                let fov_factor = (fov * 0.5).to_radians().tan();
+                let cx = (2.0 * ndcx - 1.0) * fov_factor * aspect_ratio;
+                let cy = (1.0 - 2.0 * ndcy) * fov_factor;
+                (Point::zero(), Direction::new(cx, cy, -1.0))
+            }
+            Projection::Orthographic(view_width) => {
+                let cx = (2.0 * ndcx - 1.0) * view_width * 0.5;
+                let cy = (1.0 - 2.0 * ndcy) * view_width * 0.5 / aspect_ratio;
+                (Point::new(cx, cy, 0.0), Direction::new(0.0, 0.0, -1.0))
+            }
+            Projection::Equirectangular => {
+                let phi = (2.0 * ndcx - 1.0) * f64::consts::PI;
+                let theta = (0.5 - ndcy) * f64::consts::PI;
+                let direction = Direction::new(phi.sin() * theta.cos(), theta.sin(), -phi.cos() * theta.cos());
+                (Point::zero(), direction)
+            }
+            Projection::Cubemap => {
+                let (fx, fy) = ((ndcx * 3.0).clamp(0.0, 2.999), (ndcy * 2.0).clamp(0.0, 1.999));
+                let face = fy as u32 * 3 + fx as u32;
+                let u = 2.0 * fx.fract() - 1.0;
+                let v = 1.0 - 2.0 * fy.fract();
+                (Point::zero(), cubemap_direction(face, u, v))
+            }
+            Projection::Fisheye(mapping, fov) => {
+                let radius = width.min(height) * 0.5;
+                let dx = (x - width * 0.5) / radius;
+                let dy = (height * 0.5 - y) / radius;
+                let r = dx.hypot(dy);
+                if r > 1.0 {
+                    return None;
+                }
+                let max_theta = (fov * 0.5).to_radians();
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * max_theta,
+                    FisheyeMapping::Equisolid => 2.0 * (r * (max_theta * 0.5).sin()).asin(),
+                };
+                let phi = dy.atan2(dx);
+                let direction = Direction::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), -theta.cos());
+                (Point::zero(), direction)
+            }
+        };
+        Some(ray)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use image::{ImageBuffer, Luma};
+
+    const Y_UP: Direction = Direction { x: 0.0, y: 1.0, z: 0.0 };
+
+    #[test]
+    pub fn center_ray_points_at_look_at() {
+        let c = Camera::new(
+            100.0,
+            50.0,
+            Projection::Perspective(60.0),
+            look_at_transform(Point::new(1.0, 2.0, 3.0), Point::new(1.0, 2.0, -7.0), Y_UP),
+        );
+        let r = c.pixel_ray(50.0, 25.0).unwrap();
+        assert_approx_eq!(r.origin, Point::new(1.0, 2.0, 3.0));
+        assert_approx_eq!(r.direction, Direction::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    pub fn lens_rays_converge_at_focus_distance() {
+        let mut c = Camera::new(
+            100.0,
+            100.0,
+            Projection::Perspective(90.0),
+            look_at_transform(Point::zero(), Point::new(0.0, 0.0, -1.0), Y_UP),
+        );
+        c.lens = Some(ThinLens::new(0.5, 4.0, ApertureShape::Circle));
+        for _ in 0..16 {
+            let r = c.pixel_ray(75.0, 50.0).unwrap();
+            let t = -4.0 / r.direction.z;
+            assert_approx_eq!(r.origin + r.direction * t, Point::new(2.0, 0.0, -4.0));
+            assert!(r.origin.x.hypot(r.origin.y) <= 0.25 + 1e-9);
+        }
+    }
+
+    #[test]
+    pub fn lens_system_image_is_upright() {
+        let elements = crate::lens::parse_prescription("50 5 1.5 20\n0 90 0 20", 0.01).unwrap();
+        let mut c = Camera::new(100.0, 100.0, Projection::Perspective(60.0), Matrix44f::identity());
+        c.lens_system = LensSystem::new(elements, 0.36, Some(10.0));
+        let r = (0..100).find_map(|_| c.pixel_ray(90.0, 10.0)).unwrap();
+        assert!(r.direction.x > 0.0 && r.direction.y > 0.0 && r.direction.z < 0.0);
+    }
+
+    #[test]
+    pub fn looking_straight_down_has_valid_basis() {
+        let m = look_at_transform(Point::new(0.0, 10.0, 0.0), Point::zero(), Y_UP);
+        let c = Camera::new(100.0, 100.0, Projection::Perspective(60.0), m);
+        assert_approx_eq!(c.view_direction(), Direction::new(0.0, -1.0, 0.0));
+        let r = c.pixel_ray(0.0, 0.0).unwrap();
+        assert!(!r.direction.x.is_nan() && !r.direction.y.is_nan() && !r.direction.z.is_nan());
+        let up = Direction::new(0.0, 1.0, 0.0) * m;
+        assert_approx_eq!(up.dot(c.view_direction()), 0.0);
+    }
+
+    #[test]
+    pub fn up_vector_rolls_camera() {
+        let m = look_at_transform(Point::zero(), Point::new(0.0, 0.0, -1.0), Direction::new(1.0, 0.0, 0.0));
+        assert_approx_eq!(Direction::new(0.0, 1.0, 0.0) * m, Direction::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    pub fn stereo_eyes_converge() {
+        let mut c = Camera::new(200.0, 100.0, Projection::Perspective(90.0), Matrix44f::identity());
+        c.stereo = Some(Stereo {
+            layout: StereoLayout::SideBySide,
+            interocular: 0.5,
+            convergence: 4.0,
+        });
+        let left = c.pixel_ray(50.0, 50.0).unwrap();
+        let right = c.pixel_ray(150.0, 50.0).unwrap();
+        assert_approx_eq!(left.origin, Point::new(-0.25, 0.0, 0.0));
+        assert_approx_eq!(right.origin, Point::new(0.25, 0.0, 0.0));
+        assert_approx_eq!(
+            left.origin + left.direction * (-4.0 / left.direction.z),
+            Point::new(0.0, 0.0, -4.0)
+        );
+        assert_approx_eq!(
+            right.origin + right.direction * (-4.0 / right.direction.z),
+            Point::new(0.0, 0.0, -4.0)
+        );
+    }
+
+    #[test]
+    pub fn omni_directional_stereo_eyes_are_tangent() {
+        let mut c = Camera::new(200.0, 200.0, Projection::Equirectangular, Matrix44f::identity());
+        c.stereo = Some(Stereo {
+            layout: StereoLayout::OverUnder,
+            interocular: 0.5,
+            convergence: 1.0,
+        });
+        // looking right from the left eye, which sits forward of the centre
+        let r = c.pixel_ray(150.0, 50.0).unwrap();
+        assert_approx_eq!(r.origin, Point::new(0.0, 0.0, -0.25));
+        assert_approx_eq!(r.direction, Direction::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    pub fn orthographic_rays_are_parallel() {
+        let c = Camera::new(
+            100.0,
+            50.0,
+            Projection::Orthographic(10.0),
+            look_at_transform(Point::new(0.0, 0.0, 5.0), Point::zero(), Y_UP),
+        );
+        let r = c.pixel_ray(100.0, 0.0).unwrap();
+        assert_approx_eq!(r.origin, Point::new(5.0, 2.5, 5.0));
+        assert_approx_eq!(r.direction, Direction::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    pub fn equirectangular_covers_sphere() {
+        let c = Camera::new(
+            200.0,
+            100.0,
+            Projection::Equirectangular,
+            look_at_transform(Point::zero(), Point::new(0.0, 0.0, -1.0), Y_UP),
+        );
+        assert_approx_eq!(
+            c.pixel_ray(100.0, 50.0).unwrap().direction,
+            Direction::new(0.0, 0.0, -1.0)
+        );
+        assert_approx_eq!(
+            c.pixel_ray(150.0, 50.0).unwrap().direction,
+            Direction::new(1.0, 0.0, 0.0)
+        );
+        assert_approx_eq!(c.pixel_ray(0.0, 50.0).unwrap().direction, Direction::new(0.0, 0.0, 1.0));
+        assert_approx_eq!(
+            c.pixel_ray(100.0, 0.0).unwrap().direction,
+            Direction::new(0.0, 1.0, 0.0)
+        );
+    }
+
+    #[test]
+    pub fn cubemap_face_centres() {
+        let c = Camera::new(
+            300.0,
+            200.0,
+            Projection::Cubemap,
+            look_at_transform(Point::zero(), Point::new(0.0, 0.0, -1.0), Y_UP),
+        );
+        let centre = |col: f64, row: f64| c.pixel_ray(col * 100.0 + 50.0, row * 100.0 + 50.0).unwrap().direction;
+        assert_approx_eq!(centre(0.0, 0.0), Direction::new(1.0, 0.0, 0.0));
+        assert_approx_eq!(centre(1.0, 0.0), Direction::new(-1.0, 0.0, 0.0));
+        assert_approx_eq!(centre(2.0, 0.0), Direction::new(0.0, 1.0, 0.0));
+        assert_approx_eq!(centre(0.0, 1.0), Direction::new(0.0, -1.0, 0.0));
+        assert_approx_eq!(centre(1.0, 1.0), Direction::new(0.0, 0.0, -1.0));
+        assert_approx_eq!(centre(2.0, 1.0), Direction::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    pub fn fisheye_edge_at_half_fov() {
+        for mapping in [FisheyeMapping::Equidistant, FisheyeMapping::Equisolid] {
+            let c = Camera::new(100.0, 100.0, Projection::Fisheye(mapping, 180.0), Matrix44f::identity());
+            assert_approx_eq!(
+                c.pixel_ray(50.0, 50.0).unwrap().direction,
+                Direction::new(0.0, 0.0, -1.0)
+            );
+            assert_approx_eq!(
+                c.pixel_ray(100.0, 50.0).unwrap().direction,
+                Direction::new(1.0, 0.0, 0.0)
+            );
+            assert!(c.pixel_ray(0.0, 0.0).is_none());
+        }
+    }
+
+    #[test]
+    pub fn exposure_doubles_with_iso_and_shutter_speed() {
+        let base = Exposure::default();
+        let faster_film = Exposure { iso: 200.0, ..base };
+        let longer_shutter = Exposure {
+            shutter_speed: base.shutter_speed * 2.0,
+            ..base
+        };
+        let one_stop_wider = Exposure {
+            f_number: base.f_number / 2.0f64.sqrt(),
+            ..base
+        };
+        assert_approx_eq!(faster_film.multiplier(), base.multiplier() * 2.0);
+        assert_approx_eq!(longer_shutter.multiplier(), base.multiplier() * 2.0);
+        assert_approx_eq!(one_stop_wider.multiplier(), base.multiplier() * 2.0);
+        assert_approx_eq!(base.aperture(0.05), 0.00625);
+    }
+
+    #[test]
+    pub fn polygon_distribution_within_polygon() {
+        // a square with its corners on the axes
+        for _ in 0..100 {
+            let (x, y) = uniform_polygon_distribution(4, 0.0);
+            assert!(x.abs() + y.abs() <= 1.0 + 1e-9);
+        }
+    }
+
+    #[test]
+    pub fn disk_distribution_within_unit_disk() {
+        for _ in 0..100 {
+            let (x, y) = uniform_disk_distribution();
+            assert!(x * x + y * y <= 1.0 + 1e-9);
+        }
+    }
+
+    fn mask(size: u32, open: impl Fn(u32, u32) -> bool) -> Option<ApertureMask> {
+        let image = ImageBuffer::from_fn(size, size, |x, y| Luma([if open(x, y) { 255u8 } else { 0 }]));
+        ApertureMask::new(&DynamicImage::ImageLuma8(image))
+    }
+
+    #[test]
+    pub fn mask_distribution_only_through_open_pixels() {
+        // a single open pixel, up and to the left of the centre, and nothing through the middle
+        let mask = mask(8, |x, y| x == 2 && y == 2).unwrap();
+        for _ in 0..200 {
+            let (x, y) = mask.sample();
+            assert!((-0.5..=-0.25).contains(&x) && (0.25..=0.5).contains(&y));
+        }
+    }
+
+    #[test]
+    pub fn mask_clipped_to_unit_disk() {
+        let open = mask(8, |_, _| true).unwrap();
+        for _ in 0..200 {
+            let (x, y) = open.sample();
+            assert!(x * x + y * y <= 1.0);
+        }
+        assert!(mask(8, |_, _| false).is_none());
+        // only the corners, which are outside the circle
+        assert!(mask(8, |x, y| (x == 0 || x == 7) && (y == 0 || y == 7)).is_none());
+    }
+}
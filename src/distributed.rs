@@ -0,0 +1,451 @@
+use std::io;
+use std::io::{BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rayon::ThreadPoolBuilder;
+
+use crate::binio;
+use crate::sdl;
+use crate::sdl::Scene;
+use crate::system::{CropWindow, Options, RenderBuffer, RenderContext, RenderProgress};
+use crate::system::{combine_render_buffers, render_stratum_parallel};
+
+// Messages sent from the coordinator to a worker.
+const MSG_JOB: u8 = 1;
+const MSG_TASK: u8 = 2;
+const MSG_DONE: u8 = 3;
+
+// Messages sent from a worker to the coordinator.
+const MSG_RESULT: u8 = 4;
+
+fn write_options<W: Write>(w: &mut W, options: &Options) -> io::Result<()> {
+    binio::write_u32(w, options.num_threads as u32)?;
+    binio::write_u32(w, options.width)?;
+    binio::write_u32(w, options.height)?;
+    binio::write_f64(w, options.bias)?;
+    binio::write_u16(w, options.max_depth)?;
+    binio::write_u16(w, options.samples)?;
+    binio::write_u8(w, options.passes as u8)?;
+    binio::write_u32(w, options.frame)?;
+    binio::write_u8(w, options.spectral as u8)?;
+    match options.crop {
+        Some(crop) => {
+            binio::write_u8(w, 1)?;
+            binio::write_u32(w, crop.x0)?;
+            binio::write_u32(w, crop.y0)?;
+            binio::write_u32(w, crop.x1)?;
+            binio::write_u32(w, crop.y1)
+        }
+        None => binio::write_u8(w, 0),
+    }
+}
+
+fn read_options<R: io::Read>(r: &mut R) -> io::Result<Options> {
+    let num_threads = binio::read_u32(r)? as usize;
+    let width = binio::read_u32(r)?;
+    let height = binio::read_u32(r)?;
+    let bias = binio::read_f64(r)?;
+    let max_depth = binio::read_u16(r)?;
+    let samples = binio::read_u16(r)?;
+    let passes = binio::read_u8(r)? != 0;
+    let frame = binio::read_u32(r)?;
+    let spectral = binio::read_u8(r)? != 0;
+    let crop = if binio::read_u8(r)? != 0 {
+        Some(CropWindow::new(
+            binio::read_u32(r)?,
+            binio::read_u32(r)?,
+            binio::read_u32(r)?,
+            binio::read_u32(r)?,
+        ))
+    } else {
+        None
+    };
+
+    Ok(Options {
+        num_threads,
+        width,
+        height,
+        bias,
+        max_depth,
+        samples,
+        passes,
+        crop,
+        frame,
+        spectral,
+    })
+}
+
+fn unexpected_message(msg: u8) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("unexpected message {}", msg))
+}
+
+/// The strata still to be rendered, shared between the threads serving each worker. A worker that
+/// runs out of strata waits until those handed to other workers are finished, since any of them may
+/// come back if its worker fails.
+struct TaskQueue {
+    state: Mutex<(Vec<(u32, u32)>, usize)>,
+    changed: Condvar,
+}
+
+impl TaskQueue {
+    fn new(tasks: Vec<(u32, u32)>) -> TaskQueue {
+        TaskQueue {
+            state: Mutex::new((tasks, 0)),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Waits for the next stratum to render, or returns `None` once every stratum is finished.
+    fn take(&self) -> Option<(u32, u32)> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let (tasks, in_flight) = &mut *state;
+            if let Some(task) = tasks.pop() {
+                *in_flight += 1;
+                return Some(task);
+            }
+            if *in_flight == 0 {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    fn finish(&self) {
+        self.state.lock().unwrap().1 -= 1;
+        self.changed.notify_all();
+    }
+
+    /// Puts back a stratum whose worker failed, for another worker to render.
+    fn fail(&self, task: (u32, u32)) {
+        let mut state = self.state.lock().unwrap();
+        state.0.push(task);
+        state.1 -= 1;
+        self.changed.notify_all();
+    }
+
+    fn is_empty(&self) -> bool {
+        self.state.lock().unwrap().0.is_empty()
+    }
+}
+
+/// Accepts up to `num_workers` connections, giving up on the rest once `timeout` has passed, in case
+/// some of the workers died before they could connect.
+fn accept_workers(listener: &TcpListener, num_workers: usize, timeout: Duration) -> io::Result<Vec<TcpStream>> {
+    let deadline = Instant::now() + timeout;
+    let mut streams = Vec::new();
+    listener.set_nonblocking(true)?;
+    while streams.len() < num_workers && Instant::now() < deadline {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                streams.push(stream);
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(10)),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(streams)
+}
+
+/// Renders the scene by handing out one stratified sample at a time to every worker that connects to
+/// `listener`, merging the linear radiance they send back. Up to `num_workers` connections are
+/// accepted within `connect_timeout` before rendering starts, and the render goes ahead with those
+/// that made it. Samples from a worker that disconnects are handed to the remaining ones. Each worker
+/// renders the rows of its samples on `options.num_threads` threads.
+pub fn render<T>(
+    options: Options,
+    scene: Scene,
+    scene_text: &str,
+    listener: TcpListener,
+    num_workers: usize,
+    connect_timeout: Duration,
+    progress: &mut Arc<Mutex<T>>,
+) where
+    T: RenderProgress + Send + 'static,
+{
+    {
+        let mut progress_guard = progress.lock().unwrap();
+        progress_guard.render_started(&options);
+    }
+
+    let render_buf = Arc::new(Mutex::new(RenderBuffer::new(&options, &scene)));
+    let context = RenderContext::new(options, scene);
+    let tasks = Arc::new(TaskQueue::new(context.strata()));
+    let scene_text = Arc::new(String::from(scene_text));
+
+    let streams = accept_workers(&listener, num_workers, connect_timeout).expect("could not accept worker connection");
+    if streams.is_empty() {
+        panic!("no workers connected");
+    }
+    if streams.len() < num_workers {
+        eprintln!("only {} of {} workers connected", streams.len(), num_workers);
+    }
+
+    let handles: Vec<thread::JoinHandle<()>> = streams
+        .into_iter()
+        .map(|stream| {
+            let tasks = tasks.clone();
+            let scene_text = scene_text.clone();
+            let render_buf = render_buf.clone();
+            let progress = progress.clone();
+            let empty_buf = RenderBuffer::new(&options, &context.scene);
+            thread::spawn(move || {
+                let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+                if let Err(err) = serve_worker(stream, &options, &scene_text, &tasks, empty_buf, &render_buf, &progress)
+                {
+                    eprintln!("worker {} failed: {}", peer, err);
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    if !tasks.is_empty() {
+        panic!("all workers failed before the render was finished");
+    }
+
+    {
+        let render_buf_guard = render_buf.lock().unwrap();
+        let mut progress_guard = progress.lock().unwrap();
+        progress_guard.render_finished(&options, &render_buf_guard);
+    }
+}
+
+fn serve_worker<T>(
+    stream: TcpStream,
+    options: &Options,
+    scene_text: &str,
+    tasks: &TaskQueue,
+    mut sample_buf: RenderBuffer,
+    render_buf: &Mutex<RenderBuffer>,
+    progress: &Mutex<T>,
+) -> io::Result<()>
+where
+    T: RenderProgress,
+{
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    binio::write_u8(&mut writer, MSG_JOB)?;
+    write_options(&mut writer, options)?;
+    binio::write_str(&mut writer, scene_text)?;
+
+    loop {
+        let (s_i, s_j) = match tasks.take() {
+            Some(task) => task,
+            None => {
+                binio::write_u8(&mut writer, MSG_DONE)?;
+                writer.flush()?;
+                return Ok(());
+            }
+        };
+
+        let result = request_sample(&mut reader, &mut writer, s_i, s_j, &mut sample_buf);
+        if let Err(err) = result {
+            tasks.fail((s_i, s_j));
+            return Err(err);
+        }
+
+        {
+            let mut render_buf_guard = render_buf.lock().unwrap();
+            combine_render_buffers(&mut render_buf_guard, &sample_buf);
+            let mut progress_guard = progress.lock().unwrap();
+            progress_guard.sample_finished(options, &render_buf_guard);
+        }
+        tasks.finish();
+    }
+}
+
+fn request_sample(
+    reader: &mut BufReader<TcpStream>,
+    writer: &mut BufWriter<TcpStream>,
+    s_i: u32,
+    s_j: u32,
+    sample_buf: &mut RenderBuffer,
+) -> io::Result<()> {
+    binio::write_u8(writer, MSG_TASK)?;
+    binio::write_u32(writer, s_i)?;
+    binio::write_u32(writer, s_j)?;
+    writer.flush()?;
+
+    match binio::read_u8(reader)? {
+        MSG_RESULT => sample_buf.read_pixels(reader),
+        msg => Err(unexpected_message(msg)),
+    }
+}
+
+/// Connects to a coordinator, renders the samples it asks for and sends back their linear radiance
+/// until it says the render is done.
+pub fn run_worker(addr: &str) -> io::Result<()> {
+    let stream = TcpStream::connect(addr)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    let context = match binio::read_u8(&mut reader)? {
+        MSG_JOB => {
+            let options = read_options(&mut reader)?;
+            let scene_text = binio::read_str(&mut reader)?;
+            let scene = sdl::parse(&options, &scene_text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            RenderContext::new(options, scene)
+        }
+        msg => return Err(unexpected_message(msg)),
+    };
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(context.options.num_threads)
+        .build()
+        .map_err(io::Error::other)?;
+
+    loop {
+        match binio::read_u8(&mut reader)? {
+            MSG_TASK => {
+                let s_i = binio::read_u32(&mut reader)?;
+                let s_j = binio::read_u32(&mut reader)?;
+                let sample_buf = pool.install(|| render_stratum_parallel(&context, s_i, s_j));
+                binio::write_u8(&mut writer, MSG_RESULT)?;
+                sample_buf.write_pixels(&mut writer)?;
+                writer.flush()?;
+            }
+            MSG_DONE => return Ok(()),
+            msg => return Err(unexpected_message(msg)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    const SCENE: &str = r#"
+        camera {
+          origin <0.0, 0.0, 0.0>
+          look_at <0.0, 0.0, -1.0>
+          fov 40
+        }
+        object {
+          sphere {
+            origin <0.0, 0.0, -3.0>
+            radius 1.0
+          }
+          material {
+            diffuse_light intensity 1 texture {
+              solid color rgb <1, 1, 1>
+            }
+          }
+        }
+    "#;
+
+    /// Keeps the number of samples in the finished render.
+    struct FinishedSamples(u32);
+
+    impl RenderProgress for FinishedSamples {
+        fn render_started(&mut self, _options: &Options) {}
+        fn sample_finished(&mut self, _options: &Options, _renderbuf: &RenderBuffer) {}
+        fn render_finished(&mut self, _options: &Options, renderbuf: &RenderBuffer) {
+            self.0 = renderbuf.samples;
+        }
+    }
+
+    /// Takes a single stratum and disconnects while rendering it, after the other workers have run
+    /// out of strata.
+    fn run_failing_worker(addr: &str) {
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream);
+        assert_eq!(binio::read_u8(&mut reader).unwrap(), MSG_JOB);
+        read_options(&mut reader).unwrap();
+        binio::read_str(&mut reader).unwrap();
+        assert_eq!(binio::read_u8(&mut reader).unwrap(), MSG_TASK);
+        thread::sleep(Duration::from_millis(500));
+    }
+
+    #[test]
+    fn samples_of_failed_worker_go_to_idle_workers() {
+        let options = test_options();
+        let scene = sdl::parse(&options, SCENE).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let failing = {
+            let addr = addr.clone();
+            thread::spawn(move || run_failing_worker(&addr))
+        };
+        // make sure the failing worker is served first, so it gets a stratum
+        thread::sleep(Duration::from_millis(100));
+        let healthy = thread::spawn(move || run_worker(&addr));
+
+        let mut progress = Arc::new(Mutex::new(FinishedSamples(0)));
+        render(
+            options,
+            scene,
+            SCENE,
+            listener,
+            2,
+            Duration::from_secs(10),
+            &mut progress,
+        );
+        failing.join().unwrap();
+        healthy.join().unwrap().unwrap();
+        assert_eq!(progress.lock().unwrap().0, 4);
+    }
+
+    #[test]
+    fn render_goes_ahead_without_workers_that_never_connect() {
+        let options = test_options();
+        let scene = sdl::parse(&options, SCENE).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let worker = thread::spawn(move || run_worker(&addr));
+
+        let mut progress = Arc::new(Mutex::new(FinishedSamples(0)));
+        render(
+            options,
+            scene,
+            SCENE,
+            listener,
+            3,
+            Duration::from_millis(200),
+            &mut progress,
+        );
+        worker.join().unwrap().unwrap();
+        assert_eq!(progress.lock().unwrap().0, 4);
+    }
+
+    #[test]
+    fn options_round_trip() {
+        let options = Options {
+            num_threads: 8,
+            width: 640,
+            height: 480,
+            bias: 1e-4,
+            max_depth: 50,
+            samples: 16,
+            passes: true,
+            crop: Some(CropWindow::new(1, 2, 3, 4)),
+            frame: 12,
+            spectral: true,
+        };
+        let mut buf: Vec<u8> = Vec::new();
+        write_options(&mut buf, &options).unwrap();
+        let read = read_options(&mut buf.as_slice()).unwrap();
+        assert_eq!(options.num_threads, read.num_threads);
+        assert_eq!(options.width, read.width);
+        assert_eq!(options.height, read.height);
+        assert_eq!(options.bias, read.bias);
+        assert_eq!(options.max_depth, read.max_depth);
+        assert_eq!(options.samples, read.samples);
+        assert_eq!(options.passes, read.passes);
+        assert_eq!(options.crop, read.crop);
+        assert_eq!(options.frame, read.frame);
+        assert_eq!(options.spectral, read.spectral);
+    }
+}
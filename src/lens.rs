@@ -0,0 +1,298 @@
+use crate::camera::uniform_disk_distribution;
+use crate::direction::{Direction, Dot};
+use crate::materials::refract;
+use crate::point::Point;
+
+/// One surface of a lens prescription, listed from the front (scene side) of the lens.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LensElement {
+    /// Radius of curvature, positive when the centre is towards the film. Zero for a flat surface,
+    /// such as the aperture stop.
+    pub radius: f64,
+    /// Distance along the axis to the next surface, or to the film for the last one.
+    pub thickness: f64,
+    /// Index of refraction of the medium behind the surface. Zero is treated as air.
+    pub ior: f64,
+    /// Diameter of the surface.
+    pub aperture: f64,
+}
+
+impl LensElement {
+    fn ior(&self) -> f64 {
+        if self.ior == 0.0 { 1.0 } else { self.ior }
+    }
+}
+
+/// Parses a lens prescription with one surface per line: radius, thickness, index of refraction and
+/// aperture, separated by whitespace. Lines starting with '#' are comments. Lengths are multiplied by
+/// `scale` to convert them to scene units.
+pub fn parse_prescription(text: &str, scale: f64) -> Result<Vec<LensElement>, String> {
+    let elements = text
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| {
+            let values = l
+                .split_whitespace()
+                .map(|v| {
+                    v.parse::<f64>()
+                        .map_err(|e| format!("invalid lens value '{}': {}", v, e))
+                })
+                .collect::<Result<Vec<f64>, String>>()?;
+            match values[..] {
+                [radius, thickness, ior, aperture] => Ok(LensElement {
+                    radius: radius * scale,
+                    thickness: thickness * scale,
+                    ior,
+                    aperture: aperture * scale,
+                }),
+                _ => Err(format!("lens surface must have 4 values: '{}'", l)),
+            }
+        })
+        .collect::<Result<Vec<LensElement>, String>>()?;
+    if elements.is_empty() {
+        return Err(String::from("lens has no surfaces"));
+    }
+    Ok(elements)
+}
+
+/// A sequence of spherical lens elements in front of the film, which lies at the origin of camera
+/// space with the lens towards -z.
+#[derive(Debug, Clone)]
+pub struct LensSystem {
+    elements: Vec<LensElement>,
+    film_width: f64,
+}
+
+impl LensSystem {
+    /// Creates a lens system focused at the given distance in front of its front surface (or at
+    /// infinity), by moving the film. Returns `None` if the lens can't focus there.
+    pub fn new(mut elements: Vec<LensElement>, film_width: f64, focus_distance: Option<f64>) -> Option<LensSystem> {
+        // trace a ray close to the axis from the focus point, and put the film where it crosses the
+        // axis behind the lens
+        elements.last_mut().unwrap().thickness = 0.0;
+        let mut lens = LensSystem { elements, film_width };
+        let h = lens.elements[0].aperture * 0.01;
+        let front_z = lens.surface_z(0);
+        let (origin, direction) = match focus_distance {
+            Some(d) => {
+                let origin = Point::new(0.0, 0.0, front_z - d);
+                (origin, (Point::new(h, 0.0, front_z) - origin).normalize())
+            }
+            None => (Point::new(h, 0.0, front_z - 1.0), Direction::new(0.0, 0.0, 1.0)),
+        };
+        let (o, d) = lens.trace_from_scene(origin, direction)?;
+        if d.x.abs() < 1e-12 {
+            return None;
+        }
+        let film_distance = o.z - o.x / d.x * d.z;
+        if film_distance.is_nan() || film_distance <= 0.0 {
+            return None;
+        }
+        lens.elements.last_mut().unwrap().thickness = film_distance;
+        Some(lens)
+    }
+
+    /// Stops the lens down to the given f-number by shrinking its aperture stop, the first flat surface
+    /// with air on both sides. Returns `None` if the lens has no aperture stop or can't open that wide.
+    pub fn with_f_number(mut self, f_number: f64) -> Option<LensSystem> {
+        let in_air = |i: usize| i == 0 || self.elements[i - 1].ior() == 1.0;
+        let stop = (0..self.elements.len())
+            .position(|i| self.elements[i].radius == 0.0 && self.elements[i].ior() == 1.0 && in_air(i))?;
+        // the entrance pupil, and with it the f-number, scales with the size of the stop
+        let widest = self.focal_length()? / self.entrance_pupil_diameter();
+        if f_number < widest {
+            return None;
+        }
+        self.elements[stop].aperture *= widest / f_number;
+        Some(self)
+    }
+
+    /// Effective focal length, from the angle at which a ray parallel to the axis and close to it
+    /// leaves the rear of the lens.
+    fn focal_length(&self) -> Option<f64> {
+        let h = self.elements[0].aperture * 0.01;
+        let origin = Point::new(h, 0.0, self.surface_z(0) - 1.0);
+        let (_, d) = self.trace_from_scene(origin, Direction::new(0.0, 0.0, 1.0))?;
+        Some(h / (d.x / d.z).abs())
+    }
+
+    /// Diameter of the beam parallel to the axis that makes it through the lens.
+    fn entrance_pupil_diameter(&self) -> f64 {
+        let front_z = self.surface_z(0);
+        let passes = |h: f64| {
+            self.trace_from_scene(Point::new(h, 0.0, front_z - 1.0), Direction::new(0.0, 0.0, 1.0))
+                .is_some()
+        };
+        let (mut lo, mut hi) = (0.0, self.elements[0].aperture * 0.5);
+        for _ in 0..50 {
+            let mid = 0.5 * (lo + hi);
+            if passes(mid) { lo = mid } else { hi = mid }
+        }
+        2.0 * lo
+    }
+
+    pub fn film_width(&self) -> f64 {
+        self.film_width
+    }
+
+    /// Axial position of the given surface.
+    fn surface_z(&self, i: usize) -> f64 {
+        -self.elements[i..].iter().map(|e| e.thickness).sum::<f64>()
+    }
+
+    /// Ray leaving the front of the lens for light arriving at the given point on the film, through a
+    /// random point on the rear element. `None` if the lens blocks it.
+    pub fn ray(&self, film_point: Point) -> Option<(Point, Direction)> {
+        let rear = self.elements.len() - 1;
+        let (x, y) = uniform_disk_distribution();
+        let r = self.elements[rear].aperture * 0.5;
+        let target = Point::new(x * r, y * r, self.surface_z(rear));
+        self.trace_from_film(film_point, (target - film_point).normalize())
+    }
+
+    fn trace_from_film(&self, mut o: Point, mut d: Direction) -> Option<(Point, Direction)> {
+        for i in (0..self.elements.len()).rev() {
+            let e = &self.elements[i];
+            let eta_t = if i > 0 { self.elements[i - 1].ior() } else { 1.0 };
+            (o, d) = self.refract_at(i, o, d, e.ior(), eta_t)?;
+        }
+        Some((o, d))
+    }
+
+    fn trace_from_scene(&self, mut o: Point, mut d: Direction) -> Option<(Point, Direction)> {
+        for i in 0..self.elements.len() {
+            let eta_i = if i > 0 { self.elements[i - 1].ior() } else { 1.0 };
+            (o, d) = self.refract_at(i, o, d, eta_i, self.elements[i].ior())?;
+        }
+        Some((o, d))
+    }
+
+    /// Passes a ray through surface `i` from a medium with index `eta_i` into one with `eta_t`.
+    fn refract_at(&self, i: usize, o: Point, d: Direction, eta_i: f64, eta_t: f64) -> Option<(Point, Direction)> {
+        let e = &self.elements[i];
+        let z = self.surface_z(i);
+        let (p, n) = if e.radius == 0.0 {
+            let t = (z - o.z) / d.z;
+            if t.is_nan() || t <= 0.0 {
+                return None;
+            }
+            (o + d * t, Direction::new(0.0, 0.0, 1.0))
+        } else {
+            let center = Point::new(0.0, 0.0, z + e.radius);
+            let oc = o - center;
+            let b = oc.dot(d);
+            let c = oc.dot(oc) - e.radius * e.radius;
+            let disc = b * b - c;
+            if disc < 0.0 {
+                return None;
+            }
+            let (t0, t1) = (-b - disc.sqrt(), -b + disc.sqrt());
+            // the surface is the half of the sphere nearest its vertex
+            let t = if (d.z > 0.0) != (e.radius < 0.0) { t0 } else { t1 };
+            if t <= 0.0 {
+                return None;
+            }
+            let p = o + d * t;
+            (p, (p - center).normalize())
+        };
+        if p.x * p.x + p.y * p.y > e.aperture * e.aperture * 0.25 {
+            return None;
+        }
+        if eta_i == eta_t {
+            return Some((p, d));
+        }
+        let n = if n.dot(d) > 0.0 { -n } else { n };
+        let refracted = refract(d, n, eta_t / eta_i);
+        if refracted == Direction::zero() {
+            // total internal reflection
+            return None;
+        }
+        Some((p, refracted.normalize()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    // a single plano-convex lens with a focal length of 100
+    const SINGLET: &str = "
+        # radius thickness ior aperture
+        50  5  1.5  20
+        0   90 0    20
+    ";
+
+    #[test]
+    pub fn parse_prescription_lines() {
+        let elements = parse_prescription(SINGLET, 2.0).unwrap();
+        assert_eq!(2, elements.len());
+        assert_eq!(
+            LensElement {
+                radius: 100.0,
+                thickness: 10.0,
+                ior: 1.5,
+                aperture: 40.0
+            },
+            elements[0]
+        );
+        assert!(parse_prescription("1 2 3", 1.0).is_err());
+        assert!(parse_prescription("# nothing", 1.0).is_err());
+    }
+
+    #[test]
+    pub fn focuses_at_infinity_near_focal_length() {
+        let lens = LensSystem::new(parse_prescription(SINGLET, 1.0).unwrap(), 36.0, None).unwrap();
+        // the back focal length of a thick plano-convex lens is f - t / n
+        let back_focal_length = 100.0 - 5.0 / 1.5;
+        assert!((lens.elements[1].thickness - back_focal_length).abs() < 0.01);
+    }
+
+    #[test]
+    pub fn focusing_closer_moves_film_back() {
+        let elements = parse_prescription(SINGLET, 1.0).unwrap();
+        let far = LensSystem::new(elements.clone(), 36.0, None).unwrap();
+        let near = LensSystem::new(elements, 36.0, Some(500.0)).unwrap();
+        assert!(near.elements[1].thickness > far.elements[1].thickness);
+    }
+
+    #[test]
+    pub fn rays_from_film_centre_meet_at_focus() {
+        let lens = LensSystem::new(parse_prescription(SINGLET, 1.0).unwrap(), 36.0, Some(500.0)).unwrap();
+        let focus_z = lens.surface_z(0) - 500.0;
+        for _ in 0..20 {
+            if let Some((o, d)) = lens.ray(Point::zero()) {
+                let p = o + d * ((focus_z - o.z) / d.z);
+                // within the blur from spherical aberration, which is much smaller than the lens
+                assert!(p.x.hypot(p.y) < 2.0);
+            }
+        }
+    }
+
+    #[test]
+    pub fn f_number_shrinks_aperture_stop() {
+        // the singlet's flat back is glass on one side, so it isn't a stop
+        let singlet = LensSystem::new(parse_prescription(SINGLET, 1.0).unwrap(), 36.0, None).unwrap();
+        assert!(singlet.with_f_number(8.0).is_none());
+
+        // with a stop in front of it, the beam is the size of the stop
+        let stopped = format!("0 1 0 10\n{}", SINGLET);
+        let lens = LensSystem::new(parse_prescription(&stopped, 1.0).unwrap(), 36.0, None).unwrap();
+        assert!((lens.focal_length().unwrap() - 100.0).abs() < 0.1);
+        assert!((lens.entrance_pupil_diameter() - 10.0).abs() < 0.1);
+        let f20 = lens.clone().with_f_number(20.0).unwrap();
+        assert!((f20.entrance_pupil_diameter() - 5.0).abs() < 0.1);
+        assert!(lens.with_f_number(5.0).is_none());
+    }
+
+    #[test]
+    pub fn centre_ray_passes_along_axis() {
+        let lens = LensSystem::new(parse_prescription(SINGLET, 1.0).unwrap(), 36.0, None).unwrap();
+        let (o, d) = lens
+            .trace_from_film(Point::zero(), Direction::new(0.0, 0.0, -1.0))
+            .unwrap();
+        assert_approx_eq!(d, Direction::new(0.0, 0.0, -1.0));
+        assert_approx_eq!(o.x, 0.0);
+    }
+}
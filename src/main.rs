@@ -14,22 +14,35 @@ extern crate wavefront_obj;
 mod test_utils;
 
 mod algebra;
+mod animation;
+mod binio;
+mod camera;
 mod color;
 mod direction;
+mod distributed;
+mod lens;
 mod materials;
 mod matrix;
+mod motion;
 mod object;
+mod passes;
 mod point;
 mod sdl;
 mod sdl_grammar;
 mod shapes;
+mod spectrum;
 mod system;
 mod texture;
 mod vector;
 
+use std::env;
 use std::fs::File;
 use std::io::Stdout;
 use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::sync::atomic::AtomicBool;
@@ -43,15 +56,21 @@ use clap::Parser;
 use pbr::ProgressBar;
 use rayon::ThreadPoolBuilder;
 
+use crate::camera::StereoLayout;
 use crate::color::Color;
+use crate::system::CropWindow;
+use crate::system::FrameRange;
 use crate::system::Options;
+use crate::system::RenderBuffer;
 use crate::system::RenderProgress;
 
 #[derive(Parser)]
 #[command(
     version = "0.1.0",
     author = "Gordon Tyler <gordon@doxxx.net>",
-    about = "Simple ray tracer"
+    about = "Simple ray tracer",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 struct CommandLineOptions {
     /// Image width
@@ -70,21 +89,116 @@ struct CommandLineOptions {
     #[arg(short('s'), long, default_value = "1", value_parser = clap::value_parser!(u16).range(1..))]
     samples: u16,
 
+    /// Write light path expression passes (emission, direct/indirect diffuse and specular, volume)
+    #[arg(long)]
+    passes: bool,
+
+    /// Only render the pixels inside the window x0,y0,x1,y1 (overrides the scene's crop option)
+    #[arg(long)]
+    crop: Option<CropWindow>,
+
+    /// Pad a cropped render with black to the full image size instead of writing only the crop window
+    #[arg(long)]
+    pad_crop: bool,
+
+    /// Distribute the render across this many local worker processes, each rendering on --threads threads
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    workers: Option<u32>,
+
+    /// Address the coordinator listens on for worker connections
+    #[arg(long, default_value = "127.0.0.1:0")]
+    listen: String,
+
+    /// Run as a worker for the coordinator at the given address
+    #[arg(long, conflicts_with = "workers")]
+    worker: Option<String>,
+
+    /// Also save the linear radiance and sample count to this file, for merging with other renders
+    #[arg(long)]
+    save_buffer: Option<String>,
+
+    /// Write each eye of a stereo camera to its own file (out.left.png and out.right.png) instead of
+    /// one combined image
+    #[arg(long)]
+    split_stereo: bool,
+
+    /// Trace light at sampled wavelengths instead of in RGB, for accurate dispersion and light spectra
+    #[arg(long)]
+    spectral: bool,
+
+    /// Render the inclusive range of animation frames start..end as a numbered image sequence
+    #[arg(long)]
+    frames: Option<FrameRange>,
+
     /// The file describing the scene to render
-    #[arg(required = true)]
-    scene: String,
+    #[arg(required_unless_present = "worker")]
+    scene: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Subcommand>,
+}
+
+#[derive(clap::Subcommand)]
+enum Subcommand {
+    /// Merge render buffers saved with --save-buffer into a single image
+    Merge {
+        /// Output image file
+        #[arg(short('o'), long, default_value = "out.png")]
+        output: String,
+
+        /// Pad a cropped render with black to the full image size
+        #[arg(long)]
+        pad_crop: bool,
+
+        /// Also save the merged render buffer to this file
+        #[arg(long)]
+        save_buffer: Option<String>,
+
+        /// The render buffer files to merge
+        #[arg(required = true)]
+        inputs: Vec<String>,
+    },
 }
 
 fn main() {
     let opts: CommandLineOptions = CommandLineOptions::parse();
 
-    let rendering_options = Options {
+    if let Some(Subcommand::Merge {
+        output,
+        pad_crop,
+        save_buffer,
+        inputs,
+    }) = opts.command
+    {
+        let merged = merge_render_buffers(&inputs);
+        println!(
+            "Merged {} samples per pixel from {} renders.",
+            merged.samples,
+            inputs.len()
+        );
+        write_render_buffer_to_files(&output, &merged, pad_crop, None);
+        if let Some(save_buffer) = save_buffer {
+            save_render_buffer(&save_buffer, &merged);
+        }
+        return;
+    }
+
+    if let Some(addr) = opts.worker {
+        distributed::run_worker(&addr).expect("worker failed");
+        return;
+    }
+
+    let mut rendering_options = Options {
         num_threads: opts.threads.unwrap_or_else(num_cpus::get),
         width: opts.width,
         height: opts.height,
         bias: 1e-4,
         max_depth: 50,
         samples: opts.samples,
+        passes: opts.passes,
+        crop: None,
+        frame: 0,
+        spectral: opts.spectral,
     };
 
     ThreadPoolBuilder::new()
@@ -92,21 +206,86 @@ fn main() {
         .build_global()
         .expect("could not configure threadpool");
 
-    let scene = {
-        let mut f = File::open(&opts.scene).expect("could not open scene file");
+    let scene_text = {
+        let mut f = File::open(opts.scene.unwrap()).expect("could not open scene file");
         let mut text = String::new();
         f.read_to_string(&mut text).expect("could not read scene file");
-        sdl::parse(&rendering_options, &text).expect("could not parse scene file")
+        text
+    };
+
+    let frames: Vec<Option<u32>> = match opts.frames {
+        Some(range) => (range.start..=range.end).map(Some).collect(),
+        None => vec![None],
     };
 
-    let mut progress = Arc::new(Mutex::new(CliRenderProgress::new("out.png")));
+    for frame in frames {
+        rendering_options.frame = frame.unwrap_or(0);
+        let scene = sdl::parse(&rendering_options, &scene_text).expect("could not parse scene file");
+
+        rendering_options.crop = opts.crop.or(scene.options.crop).map(|crop| {
+            crop.clip(rendering_options.width, rendering_options.height)
+                .expect("crop window lies outside the image")
+        });
+
+        let numbered = |filename: &str| match frame {
+            Some(frame) => frame_filename(filename, frame),
+            None => String::from(filename),
+        };
+        if let Some(frame) = frame {
+            println!("Frame {}", frame);
+        }
 
-    let (stop_ticker, progress_ticker_handle) = spawn_progress_ticker(&progress);
+        let mut progress = Arc::new(Mutex::new(CliRenderProgress::new(
+            &numbered("out.png"),
+            opts.pad_crop,
+            opts.save_buffer.as_deref().map(numbered),
+            scene.camera.stereo.filter(|_| opts.split_stereo).map(|s| s.layout),
+        )));
+
+        let (stop_ticker, progress_ticker_handle) = spawn_progress_ticker(&progress);
+
+        match opts.workers {
+            Some(num_workers) => {
+                let num_workers = num_workers as usize;
+                let listener = TcpListener::bind(&opts.listen).expect("could not listen for workers");
+                let addr = listener.local_addr().expect("could not determine coordinator address");
+                let mut children = spawn_workers(num_workers, &addr.to_string());
+                distributed::render(
+                    rendering_options,
+                    scene,
+                    &scene_text,
+                    listener,
+                    num_workers,
+                    WORKER_CONNECT_TIMEOUT,
+                    &mut progress,
+                );
+                for child in children.iter_mut() {
+                    child.wait().expect("could not wait for worker process");
+                }
+            }
+            None => system::render(rendering_options, scene, &mut progress),
+        }
 
-    system::render(rendering_options, scene, &mut progress);
+        stop_ticker.store(true, Ordering::Relaxed);
+        progress_ticker_handle.join().unwrap();
+    }
+}
 
-    stop_ticker.store(true, Ordering::Relaxed);
-    progress_ticker_handle.join().unwrap();
+/// How long to wait for the spawned workers to connect, in case some of them fail to start.
+const WORKER_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn spawn_workers(num_workers: usize, addr: &str) -> Vec<Child> {
+    let exe = env::current_exe().expect("could not determine path of raytracer executable");
+    (0..num_workers)
+        .map(|_| {
+            Command::new(&exe)
+                .arg("--worker")
+                .arg(addr)
+                .stdout(Stdio::null())
+                .spawn()
+                .expect("could not start worker process")
+        })
+        .collect()
 }
 
 fn spawn_progress_ticker(progress: &Arc<Mutex<CliRenderProgress>>) -> (Arc<AtomicBool>, JoinHandle<()>) {
@@ -136,24 +315,40 @@ struct CliRenderProgress {
     steady_start_time: time::SteadyTime,
     pb: ProgressBar<Stdout>,
     last_output_time: time::SteadyTime,
-    num_samples: u16,
+    pad_crop: bool,
+    buffer_filename: Option<String>,
+    split_stereo: Option<StereoLayout>,
 }
 
 impl CliRenderProgress {
-    fn new(filename: &str) -> CliRenderProgress {
+    fn new(
+        filename: &str,
+        pad_crop: bool,
+        buffer_filename: Option<String>,
+        split_stereo: Option<StereoLayout>,
+    ) -> CliRenderProgress {
         CliRenderProgress {
             filename: String::from(filename),
             start_time: time::now(),
             steady_start_time: time::SteadyTime::now(),
             pb: ProgressBar::new(0),
             last_output_time: time::SteadyTime::now(),
-            num_samples: 0,
+            pad_crop,
+            buffer_filename,
+            split_stereo,
         }
     }
 
     fn tick(&mut self) {
         self.pb.tick();
     }
+
+    fn write_output(&self, renderbuf: &RenderBuffer) {
+        write_render_buffer_to_files(&self.filename, renderbuf, self.pad_crop, self.split_stereo);
+        if let Some(buffer_filename) = &self.buffer_filename {
+            save_render_buffer(buffer_filename, renderbuf);
+        }
+    }
 }
 
 impl RenderProgress for CliRenderProgress {
@@ -162,6 +357,15 @@ impl RenderProgress for CliRenderProgress {
             "Rendering {}x{}, {} samples per pixel, using {} threads.",
             options.width, options.height, options.samples, options.num_threads
         );
+        if let Some(crop) = options.crop {
+            println!(
+                "Cropped to {}x{} at ({}, {}).",
+                crop.width(),
+                crop.height(),
+                crop.x0,
+                crop.y0
+            );
+        }
         println!("Started at {}", self.start_time.rfc822());
 
         // Trigger initial progress bar draw
@@ -171,21 +375,19 @@ impl RenderProgress for CliRenderProgress {
         self.pb.set(0);
     }
 
-    fn sample_finished(&mut self, options: &Options, renderbuf: &Vec<Vec<Color>>) {
-        self.num_samples += 1;
-
+    fn sample_finished(&mut self, _options: &Options, renderbuf: &RenderBuffer) {
         let now = time::SteadyTime::now();
         if (now - self.last_output_time).num_milliseconds() >= 5000 {
             self.last_output_time = now;
 
-            write_render_result_to_file(&options, &self.filename, &renderbuf, self.num_samples);
+            self.write_output(renderbuf);
         }
 
         self.pb.inc();
     }
 
-    fn render_finished(&mut self, options: &Options, renderbuf: &Vec<Vec<Color>>) {
-        write_render_result_to_file(&options, &self.filename, &renderbuf, self.num_samples);
+    fn render_finished(&mut self, _options: &Options, renderbuf: &RenderBuffer) {
+        self.write_output(renderbuf);
 
         let end_time = time::now();
         let elapsed = time::SteadyTime::now() - self.steady_start_time;
@@ -208,25 +410,116 @@ fn color_to_rgb(v: Color) -> image::Rgb<u8> {
 fn convert_render_result_to_image(
     renderbuf: &Vec<Vec<Color>>,
     num_samples: f64,
+    offset: (u32, u32),
     imgbuf: &mut image::ImageBuffer<image::Rgb<u8>, Vec<u8>>,
 ) {
-    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
-        let row = &renderbuf[y as usize];
-        let c = (row[x as usize] / num_samples).gamma_2();
-        *pixel = color_to_rgb(c);
+    for (y, row) in renderbuf.iter().enumerate() {
+        for (x, &v) in row.iter().enumerate() {
+            let c = (v / num_samples).gamma_2();
+            imgbuf.put_pixel(x as u32 + offset.0, y as u32 + offset.1, color_to_rgb(c));
+        }
     }
 }
 
-fn write_render_result_to_file(options: &Options, filename: &str, renderbuf: &Vec<Vec<Color>>, current_sample: u16) {
-    let mut imgbuf = image::RgbImage::new(options.width, options.height);
-    convert_render_result_to_image(&renderbuf, (current_sample + 1) as f64, &mut imgbuf);
+fn write_render_result_to_file(
+    renderbuf: &RenderBuffer,
+    buf: &Vec<Vec<Color>>,
+    filename: &str,
+    pad_crop: bool,
+    split_stereo: Option<StereoLayout>,
+) {
+    // the eyes can only be told apart in the full frame
+    let pad_crop = pad_crop || split_stereo.is_some();
+    let window = renderbuf.window;
+    let mut imgbuf = if pad_crop {
+        image::RgbImage::new(renderbuf.width, renderbuf.height)
+    } else {
+        image::RgbImage::new(window.width(), window.height())
+    };
+    let offset = if pad_crop { (window.x0, window.y0) } else { (0, 0) };
+    convert_render_result_to_image(buf, renderbuf.samples.max(1) as f64, offset, &mut imgbuf);
+
+    match split_stereo {
+        Some(layout) => {
+            let (w, h) = imgbuf.dimensions();
+            let (left, right) = match layout {
+                StereoLayout::SideBySide => ((0, 0, w / 2, h), (w / 2, 0, w - w / 2, h)),
+                StereoLayout::OverUnder => ((0, 0, w, h / 2), (0, h / 2, w, h - h / 2)),
+            };
+            for (eye, (x, y, w, h)) in [("left", left), ("right", right)] {
+                let eyebuf = image::imageops::crop(&mut imgbuf, x, y, w, h).to_image();
+                save_image(eyebuf, &layer_filename(filename, eye));
+            }
+        }
+        None => save_image(imgbuf, filename),
+    }
+}
 
+fn save_image(imgbuf: image::RgbImage, filename: &str) {
     let ref mut fout = File::create(filename).expect("Could not open output file");
     image::ImageRgb8(imgbuf)
         .save(fout, image::PNG)
         .expect("Could not write render result to output file");
 }
 
+fn write_render_buffer_to_files(
+    filename: &str,
+    renderbuf: &RenderBuffer,
+    pad_crop: bool,
+    split_stereo: Option<StereoLayout>,
+) {
+    write_render_result_to_file(renderbuf, &renderbuf.beauty, filename, pad_crop, split_stereo);
+    for layer in renderbuf.layers() {
+        let layer_filename = layer_filename(filename, &layer.name);
+        write_render_result_to_file(renderbuf, &layer.buf, &layer_filename, pad_crop, split_stereo);
+    }
+}
+
+fn save_render_buffer(filename: &str, renderbuf: &RenderBuffer) {
+    let mut fout = BufWriter::new(File::create(filename).expect("Could not open render buffer file"));
+    renderbuf
+        .save(&mut fout)
+        .and_then(|_| fout.flush())
+        .expect("Could not write render buffer file");
+}
+
+fn load_render_buffer(filename: &str) -> RenderBuffer {
+    let mut fin = BufReader::new(File::open(filename).expect("Could not open render buffer file"));
+    RenderBuffer::load(&mut fin).expect("Could not read render buffer file")
+}
+
+/// Combines render buffers saved by independent renders of the same scene, weighting each by the
+/// number of samples it holds.
+fn merge_render_buffers(inputs: &[String]) -> RenderBuffer {
+    let mut merged = load_render_buffer(&inputs[0]);
+    for input in &inputs[1..] {
+        let renderbuf = load_render_buffer(input);
+        if !merged.is_compatible(&renderbuf) {
+            panic!(
+                "{} does not have the same image size, crop window and layers as {}",
+                input, inputs[0]
+            );
+        }
+        system::combine_render_buffers(&mut merged, &renderbuf);
+    }
+    merged
+}
+
+/// Maps "out.png" to "out.0012.png" for frame 12.
+fn frame_filename(filename: &str, frame: u32) -> String {
+    layer_filename(filename, &format!("{:04}", frame))
+}
+
+/// Derives the output filename for an extra render layer, e.g. `out.png` -> `out.volume.png`.
+fn layer_filename(filename: &str, layer: &str) -> String {
+    let path = Path::new(filename);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("out");
+    let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("png");
+    path.with_file_name(format!("{}.{}.{}", stem, layer, ext))
+        .to_string_lossy()
+        .into_owned()
+}
+
 fn format_duration(mut d: time::Duration) -> String {
     let mut s = String::new();
     let hours = d.num_hours();
@@ -0,0 +1,93 @@
+use rand::Rng;
+
+use crate::color::Color;
+use crate::direction::Dot;
+use crate::materials::dielectric::{Absorption, fresnel};
+use crate::materials::{Material, ScatterKind, ScatteredRay};
+use crate::spectrum::Wavelengths;
+use crate::system::{RayHit, RenderContext};
+
+/// A thin, smooth dielectric coating over another material, like varnish or the clear coat of car
+/// paint. Light either reflects off the coating or passes through it to the base, and may be absorbed
+/// by the coating on the way in and back out.
+#[derive(Clone)]
+pub struct Coated {
+    ior: f64,
+    /// Thickness of the coating and how it absorbs light.
+    absorption: Option<(f64, Absorption)>,
+    base: Box<dyn Material>,
+}
+
+impl Coated {
+    pub fn new(ior: f64, absorption: Option<(f64, Absorption)>, base: Box<dyn Material>) -> Coated {
+        Coated { ior, absorption, base }
+    }
+
+    /// Distance travelled through the coating by light crossing it at an angle with the given cosine
+    /// outside.
+    fn path_length(&self, thickness: f64, cos: f64) -> f64 {
+        let sin2 = (1.0 - cos * cos) / (self.ior * self.ior);
+        thickness / (1.0 - sin2).max(1e-6).sqrt()
+    }
+}
+
+impl Material for Coated {
+    fn scatter(&self, context: &RenderContext, hit: &RayHit) -> Option<ScatteredRay> {
+        let incident = hit.incident.direction;
+        // rays from inside the object never meet the coating
+        if incident.dot(hit.n) > 0.0 {
+            return self.base.scatter(context, hit);
+        }
+
+        if rand::rng().random::<f64>() < fresnel(incident, hit.n, self.ior) {
+            return Some(ScatteredRay {
+                kind: ScatterKind::Specular,
+                attenuation: Color::white(),
+                origin: hit.point() + hit.n * context.options.bias,
+                direction: incident.reflect(hit.n).normalize(),
+                wavelength: hit.incident.wavelength,
+            });
+        }
+
+        let mut scattered = self.base.scatter(context, hit)?;
+        if scattered.direction.dot(hit.n) > 0.0 {
+            // the part of the light leaving the base that makes it back out through the coating
+            scattered.attenuation = scattered.attenuation * (1.0 - fresnel(-scattered.direction, hit.n, self.ior));
+            if let Some((thickness, absorption)) = &self.absorption {
+                let distance = self.path_length(*thickness, -incident.dot(hit.n))
+                    + self.path_length(*thickness, scattered.direction.dot(hit.n));
+                scattered.attenuation = scattered.attenuation * absorption.transmittance_at(distance);
+            }
+        }
+        Some(scattered)
+    }
+
+    fn emit(&self, context: &RenderContext, hit: &RayHit) -> Color {
+        self.base.emit(context, hit)
+    }
+
+    fn emit_spectrum(&self, context: &RenderContext, hit: &RayHit, wavelengths: &Wavelengths) -> Color {
+        self.base.emit_spectrum(context, hit, wavelengths)
+    }
+
+    fn box_clone(&self) -> Box<dyn Material> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Lambertian;
+    use crate::test_utils::*;
+    use crate::texture::Texture;
+
+    #[test]
+    pub fn path_length_through_coating() {
+        let coated = Coated::new(1.5, None, Box::new(Lambertian::new(Texture::Solid(Color::white()))));
+        assert_approx_eq!(coated.path_length(0.1, 1.0), 0.1);
+        // at grazing incidence light is refracted to the critical angle
+        let cos_critical = (1.0 - 1.0 / (1.5 * 1.5_f64)).sqrt();
+        assert_approx_eq!(coated.path_length(0.1, 0.0), 0.1 / cos_critical);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::color::Color;
+use crate::direction::Dot;
+use crate::materials::Material;
+use crate::materials::microfacet::{Microfacet, ShadingFrame};
+use crate::materials::{ScatterKind, ScatteredRay};
+use crate::system::{RayHit, RenderContext};
+
+/// Metals with measured complex indices of refraction.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ConductorPreset {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+impl ConductorPreset {
+    /// The real (eta) and imaginary (k) parts of the index of refraction for red, green and blue.
+    pub fn ior(self) -> (Color, Color) {
+        match self {
+            ConductorPreset::Gold => (Color::new(0.143, 0.374, 1.442), Color::new(3.983, 2.385, 1.603)),
+            ConductorPreset::Copper => (Color::new(0.200, 0.924, 1.102), Color::new(3.912, 2.452, 2.142)),
+            ConductorPreset::Aluminium => (Color::new(1.657, 0.880, 0.521), Color::new(9.224, 6.270, 4.837)),
+            ConductorPreset::Silver => (Color::new(0.155, 0.117, 0.138), Color::new(4.828, 3.122, 2.147)),
+        }
+    }
+}
+
+/// A rough metal, reflecting light off microfacets with a Fresnel term from its complex index of
+/// refraction.
+#[derive(Clone)]
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    microfacet: Microfacet,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, microfacet: Microfacet) -> Conductor {
+        Conductor { eta, k, microfacet }
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, context: &RenderContext, hit: &RayHit) -> Option<ScatteredRay> {
+        let n = if hit.incident.direction.dot(hit.n) > 0.0 {
+            -hit.n
+        } else {
+            hit.n
+        };
+        let frame = ShadingFrame::new(n);
+        let wo = frame.to_local(-hit.incident.direction);
+        let m = self.microfacet.sample_normal();
+        let wi = (-wo).reflect(m);
+        let weight = self.microfacet.scatter_weight(wo, wi, m);
+        if weight == 0.0 {
+            return None;
+        }
+
+        let cos_i = wo.dot(m);
+        let fresnel = Color::new(
+            fresnel_conductor(cos_i, self.eta.r, self.k.r),
+            fresnel_conductor(cos_i, self.eta.g, self.k.g),
+            fresnel_conductor(cos_i, self.eta.b, self.k.b),
+        );
+        Some(ScatteredRay {
+            kind: ScatterKind::Specular,
+            attenuation: fresnel * weight,
+            origin: hit.point() + n * context.options.bias,
+            direction: frame.to_world(wi).normalize(),
+            wavelength: hit.incident.wavelength,
+        })
+    }
+
+    fn emit(&self, _context: &RenderContext, _hit: &RayHit) -> Color {
+        Color::black()
+    }
+
+    fn box_clone(&self) -> Box<dyn Material> {
+        Box::new(self.clone())
+    }
+}
+
+/// Unpolarized reflectance of a conductor with the complex index of refraction `eta + k i`, for
+/// light arriving at an angle with the given cosine.
+pub fn fresnel_conductor(cos_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = (cos_i * cos_i).min(1.0);
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let t1 = a2b2 + cos2;
+    let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_i.abs() * a;
+    let r_s = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let r_p = r_s * (t3 - t4) / (t3 + t4);
+    0.5 * (r_s + r_p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    pub fn fresnel_at_normal_incidence() {
+        // ((eta - 1)^2 + k^2) / ((eta + 1)^2 + k^2)
+        let (eta, k) = (0.2, 3.9);
+        let expected = ((eta - 1.0) * (eta - 1.0) + k * k) / ((eta + 1.0) * (eta + 1.0) + k * k);
+        assert_approx_eq!(fresnel_conductor(1.0, eta, k), expected);
+    }
+
+    #[test]
+    pub fn fresnel_reaches_one_at_grazing_angle() {
+        let (eta, k) = ConductorPreset::Gold.ior();
+        assert!(fresnel_conductor(1e-6, eta.b, k.b) > 0.99);
+        assert!(fresnel_conductor(1.0, eta.r, k.r) > fresnel_conductor(1.0, eta.b, k.b));
+    }
+}
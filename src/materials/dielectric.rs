@@ -6,52 +6,184 @@ use rand::Rng;
 use crate::color::Color;
 use crate::direction::{Direction, Dot};
 use crate::materials::Material;
-use crate::materials::ScatteredRay;
+use crate::materials::microfacet::{Microfacet, ShadingFrame};
+use crate::materials::{ScatterKind, ScatteredRay};
+use crate::spectrum;
 use crate::system::{RayHit, RenderContext};
 
+/// Wavelength of the helium d line in nanometres, where glass catalogues give refractive indices.
+const HELIUM_D: f64 = 587.56;
+/// Wavelengths of the hydrogen F and C lines in nanometres, which define the Abbe number.
+const HYDROGEN_F: f64 = 486.13;
+const HYDROGEN_C: f64 = 656.27;
+
+/// Index of refraction, which may vary with wavelength to disperse light into its colours.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Ior {
+    Constant(f64),
+    /// Cauchy's equation `A + B / λ²` with λ in micrometres.
+    Cauchy(f64, f64),
+    /// Sellmeier equation with its B and C coefficients, C in square micrometres.
+    Sellmeier([f64; 3], [f64; 3]),
+}
+
+impl Ior {
+    /// Cauchy's equation fitted to a refractive index at the helium d line and an Abbe number.
+    pub fn from_abbe(nd: f64, vd: f64) -> Ior {
+        let (d, f, c) = (HELIUM_D / 1000.0, HYDROGEN_F / 1000.0, HYDROGEN_C / 1000.0);
+        let b = (nd - 1.0) / (vd * (1.0 / (f * f) - 1.0 / (c * c)));
+        Ior::Cauchy(nd - b / (d * d), b)
+    }
+
+    /// Index of refraction at the given wavelength in nanometres.
+    pub fn at(&self, wavelength: f64) -> f64 {
+        let l2 = (wavelength / 1000.0).powi(2);
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy(a, b) => a + b / l2,
+            Ior::Sellmeier(b, c) => (1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>()).sqrt(),
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+}
+
+/// Light absorbed by a medium as it passes through, following the Beer-Lambert law.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Absorption {
+    /// Color of white light after travelling `distance` through the medium.
+    pub transmittance: Color,
+    pub distance: f64,
+}
+
+impl Absorption {
+    /// Fraction of the light left after travelling the given distance.
+    pub fn transmittance_at(&self, distance: f64) -> Color {
+        let t = distance / self.distance;
+        Color::new(
+            self.transmittance.r.powf(t),
+            self.transmittance.g.powf(t),
+            self.transmittance.b.powf(t),
+        )
+    }
+}
+
+/// Glass and other transparent materials. Without a microfacet distribution the surface is perfectly
+/// smooth, and without absorption the medium inside is clear. A dispersive index of refraction limits
+/// each path through it to a single wavelength.
 #[derive(Clone)]
 pub struct Dielectric {
-    ior: f64,
-    fuzz: f64,
+    ior: Ior,
+    microfacet: Option<Microfacet>,
+    absorption: Option<Absorption>,
 }
 
 impl Dielectric {
-    pub fn new(ior: f64, fuzz: f64) -> Dielectric {
-        Dielectric { ior, fuzz }
+    pub fn new(ior: Ior, microfacet: Option<Microfacet>, absorption: Option<Absorption>) -> Dielectric {
+        Dielectric {
+            ior,
+            microfacet,
+            absorption,
+        }
     }
-}
 
-impl Material for Dielectric {
-    fn scatter(&self, context: &RenderContext, hit: &RayHit) -> Option<ScatteredRay> {
+    fn scatter_smooth(&self, context: &RenderContext, hit: &RayHit, ior: f64) -> Option<ScatteredRay> {
         let p = hit.point();
         let outside = hit.incident.direction.dot(hit.n) < 0.0;
         let bias = hit.n * context.options.bias;
 
-        let kr = fresnel(hit.incident.direction, hit.n, self.ior);
+        let kr = fresnel(hit.incident.direction, hit.n, ior);
         let mut rng = rand::rng();
         if rng.random::<f64>() < kr {
             // reflection
             let reflected = hit.incident.direction.reflect(hit.n);
-            let fuzz = self.fuzz * Direction::uniform_sphere_distribution();
-            let scattered = (reflected + fuzz).normalize();
             Some(ScatteredRay {
+                kind: ScatterKind::Specular,
                 attenuation: Color::white(),
                 origin: if outside { p + bias } else { p - bias },
-                direction: scattered,
+                direction: reflected.normalize(),
+                wavelength: hit.incident.wavelength,
             })
         } else {
             // refraction
-            let refracted = refract(hit.incident.direction, hit.n, self.ior);
-            let fuzz = self.fuzz * Direction::uniform_sphere_distribution();
-            let scattered = (refracted + fuzz).normalize();
+            let refracted = refract(hit.incident.direction, hit.n, ior);
             Some(ScatteredRay {
+                kind: ScatterKind::Specular,
                 attenuation: Color::white(),
                 origin: if outside { p - bias } else { p + bias },
-                direction: scattered,
+                direction: refracted.normalize(),
+                wavelength: hit.incident.wavelength,
             })
         }
     }
 
+    /// Reflects or refracts through a facet picked from the microfacet distribution (Walter et al.,
+    /// "Microfacet Models for Refraction through Rough Surfaces").
+    fn scatter_rough(
+        &self,
+        context: &RenderContext,
+        hit: &RayHit,
+        microfacet: &Microfacet,
+        ior: f64,
+    ) -> Option<ScatteredRay> {
+        let p = hit.point();
+        let incident = hit.incident.direction;
+        let outside = incident.dot(hit.n) < 0.0;
+        // the shading frame is on the side the ray arrives from, while fresnel and refract expect
+        // the outward facing normal
+        let n = if outside { hit.n } else { -hit.n };
+        let frame = ShadingFrame::new(n);
+        let wo = frame.to_local(-incident);
+        let m_local = microfacet.sample_normal();
+        let m = frame.to_world(m_local);
+        let m_outward = if outside { m } else { -m };
+        let bias = n * context.options.bias;
+
+        let mut rng = rand::rng();
+        let (direction, origin) = if rng.random::<f64>() < fresnel(incident, m_outward, ior) {
+            (incident.reflect(m), p + bias)
+        } else {
+            (refract(incident, m_outward, ior), p - bias)
+        };
+        let weight = microfacet.scatter_weight(wo, frame.to_local(direction), m_local);
+        if weight == 0.0 {
+            return None;
+        }
+        Some(ScatteredRay {
+            kind: ScatterKind::Specular,
+            attenuation: Color::white() * weight,
+            origin,
+            direction: direction.normalize(),
+            wavelength: hit.incident.wavelength,
+        })
+    }
+}
+
+impl Material for Dielectric {
+    fn scatter(&self, context: &RenderContext, hit: &RayHit) -> Option<ScatteredRay> {
+        // a dispersive material picks the wavelength for the rest of the path, which carries the
+        // colour of that wavelength. Spectral paths keep their hero wavelength instead.
+        let (wavelength, tint) = spectrum::path_wavelength(self.ior.is_dispersive(), hit.incident);
+        let ior = self.ior.at(wavelength.unwrap_or(HELIUM_D));
+        let mut scattered = match &self.microfacet {
+            Some(microfacet) => self.scatter_rough(context, hit, microfacet, ior)?,
+            None => self.scatter_smooth(context, hit, ior)?,
+        };
+        scattered.wavelength = wavelength;
+        scattered.attenuation = scattered.attenuation * tint;
+        if let Some(absorption) = &self.absorption {
+            // a ray hitting the surface from inside has travelled through the medium from where it
+            // entered, or from its last internal reflection
+            if hit.incident.direction.dot(hit.n) > 0.0 {
+                let distance = (hit.point() - hit.incident.origin).length();
+                scattered.attenuation = scattered.attenuation * absorption.transmittance_at(distance);
+            }
+        }
+        Some(scattered)
+    }
+
     fn emit(&self, _context: &RenderContext, _hit: &RayHit) -> Color {
         Color::black()
     }
@@ -65,7 +197,9 @@ fn clamp(lo: f64, hi: f64, val: f64) -> f64 {
     lo.max(hi.min(val))
 }
 
-fn refract(incident: Direction, normal: Direction, ior: f64) -> Direction {
+/// Direction of a ray refracted at a surface with the given relative index of refraction, or zero
+/// on total internal reflection.
+pub fn refract(incident: Direction, normal: Direction, ior: f64) -> Direction {
     let mut cos_i = clamp(-1.0, 1.0, incident.dot(normal));
     let mut eta_i = 1.0;
     let mut eta_t = ior;
@@ -86,7 +220,7 @@ fn refract(incident: Direction, normal: Direction, ior: f64) -> Direction {
 }
 
 /// incident, normal, index of reflection -> reflection factor
-fn fresnel(incident: Direction, normal: Direction, ior: f64) -> f64 {
+pub fn fresnel(incident: Direction, normal: Direction, ior: f64) -> f64 {
     let mut cos_i = clamp(-1.0, 1.0, incident.dot(normal));
     let mut eta_i = 1.0;
     let mut eta_t = ior;
@@ -106,3 +240,40 @@ fn fresnel(incident: Direction, normal: Direction, ior: f64) -> f64 {
         (r_s * r_s + r_p * r_p) / 2.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    pub fn abbe_number_fit() {
+        // N-BK7
+        let ior = Ior::from_abbe(1.5168, 64.17);
+        assert_approx_eq!(ior.at(HELIUM_D), 1.5168);
+        let vd = (ior.at(HELIUM_D) - 1.0) / (ior.at(HYDROGEN_F) - ior.at(HYDROGEN_C));
+        assert!((vd - 64.17).abs() < 1e-6);
+    }
+
+    #[test]
+    pub fn sellmeier_matches_catalogue() {
+        // N-BK7 from the Schott catalogue
+        let ior = Ior::Sellmeier(
+            [1.03961212, 0.231792344, 1.01046945],
+            [0.00600069867, 0.0200179144, 103.560653],
+        );
+        assert!((ior.at(HELIUM_D) - 1.5168).abs() < 1e-4);
+        assert!(ior.at(450.0) > ior.at(650.0));
+    }
+
+    #[test]
+    pub fn absorption_compounds_with_distance() {
+        let a = Absorption {
+            transmittance: Color::new(0.5, 1.0, 0.8),
+            distance: 2.0,
+        };
+        assert_approx_eq!(a.transmittance_at(0.0), Color::white());
+        assert_approx_eq!(a.transmittance_at(2.0), Color::new(0.5, 1.0, 0.8));
+        assert_approx_eq!(a.transmittance_at(4.0), Color::new(0.25, 1.0, 0.64));
+    }
+}
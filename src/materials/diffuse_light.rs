@@ -1,6 +1,7 @@
 use crate::color::Color;
 use crate::materials::Material;
 use crate::materials::ScatteredRay;
+use crate::spectrum::{Blackbody, Wavelengths};
 use crate::system::{RayHit, RenderContext};
 use crate::texture::{ColorSource, Texture};
 
@@ -8,11 +9,26 @@ use crate::texture::{ColorSource, Texture};
 pub struct DiffuseLight {
     intensity: f64,
     texture: Texture,
+    spectrum: Option<Blackbody>,
 }
 
 impl DiffuseLight {
     pub fn new(intensity: f64, texture: Texture) -> DiffuseLight {
-        DiffuseLight { intensity, texture }
+        DiffuseLight {
+            intensity,
+            texture,
+            spectrum: None,
+        }
+    }
+
+    /// A light with the spectrum of a black body at the given temperature in kelvin.
+    pub fn blackbody(intensity: f64, temperature: f64) -> DiffuseLight {
+        let blackbody = Blackbody::new(temperature);
+        DiffuseLight {
+            intensity,
+            texture: Texture::Solid(blackbody.color()),
+            spectrum: Some(blackbody),
+        }
     }
 }
 
@@ -25,6 +41,13 @@ impl Material for DiffuseLight {
         self.intensity * self.texture.color_at_uv(hit.uv)
     }
 
+    fn emit_spectrum(&self, context: &RenderContext, hit: &RayHit, wavelengths: &Wavelengths) -> Color {
+        match &self.spectrum {
+            Some(blackbody) => self.intensity * wavelengths.sample_spectrum(|w| blackbody.value(w)),
+            None => wavelengths.upsample(self.emit(context, hit)),
+        }
+    }
+
     fn box_clone(&self) -> Box<dyn Material> {
         Box::new(self.clone())
     }
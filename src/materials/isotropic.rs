@@ -1,7 +1,7 @@
 use crate::color::Color;
 use crate::direction::Direction;
 use crate::materials::Material;
-use crate::materials::ScatteredRay;
+use crate::materials::{ScatterKind, ScatteredRay};
 use crate::system::{RayHit, RenderContext};
 use crate::texture::{ColorSource, Texture};
 
@@ -19,9 +19,11 @@ impl Isotropic {
 impl Material for Isotropic {
     fn scatter(&self, _context: &RenderContext, hit: &RayHit) -> Option<ScatteredRay> {
         Some(ScatteredRay {
+            kind: ScatterKind::Volume,
             attenuation: self.texture.color_at_uv(hit.uv),
             origin: hit.point(),
             direction: Direction::uniform_sphere_distribution(),
+            wavelength: hit.incident.wavelength,
         })
     }
 
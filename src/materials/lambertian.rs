@@ -1,7 +1,7 @@
 use crate::color::Color;
 use crate::direction::Direction;
 use crate::materials::Material;
-use crate::materials::ScatteredRay;
+use crate::materials::{ScatterKind, ScatteredRay};
 use crate::system::{RayHit, RenderContext};
 use crate::texture::{ColorSource, Texture};
 
@@ -24,9 +24,11 @@ impl Material for Lambertian {
         let scattered_dir = (target - p).normalize();
 
         Some(ScatteredRay {
+            kind: ScatterKind::Diffuse,
             attenuation: self.texture.color_at_uv(hit.uv),
             origin: scattered_origin,
             direction: scattered_dir,
+            wavelength: hit.incident.wavelength,
         })
     }
 
@@ -1,7 +1,7 @@
 use crate::color::Color;
 use crate::direction::Direction;
 use crate::materials::Material;
-use crate::materials::ScatteredRay;
+use crate::materials::{ScatterKind, ScatteredRay};
 use crate::system::{RayHit, RenderContext};
 use crate::texture::{ColorSource, Texture};
 
@@ -25,9 +25,11 @@ impl Material for Metal {
         let scattered_dir = (reflected + fuzz).normalize();
 
         Some(ScatteredRay {
+            kind: ScatterKind::Specular,
             attenuation: self.texture.color_at_uv(hit.uv),
             origin: scattered_origin,
             direction: scattered_dir,
+            wavelength: hit.incident.wavelength,
         })
     }
 
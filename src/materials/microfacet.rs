@@ -0,0 +1,203 @@
+use std::f64::consts::PI;
+
+use rand::Rng;
+
+use crate::direction::{Direction, Dot};
+
+/// Distribution of microfacet normals across a rough surface.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Distribution {
+    Ggx,
+    Beckmann,
+}
+
+/// A rough surface made of tiny mirror facets. Directions are in the local shading frame, where the
+/// surface normal is +z and the tangent is +x.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Microfacet {
+    pub distribution: Distribution,
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl Microfacet {
+    /// Roughness is perceptually linear, from 0 (mirror) to 1. Anisotropy from -1 to 1 stretches the
+    /// highlight along the tangent (positive) or the bitangent (negative).
+    pub fn new(distribution: Distribution, roughness: f64, anisotropy: f64) -> Microfacet {
+        let alpha = (roughness * roughness).max(1e-4);
+        let aspect = (1.0 - 0.9 * anisotropy.abs()).sqrt();
+        let (alpha_x, alpha_y) = if anisotropy >= 0.0 {
+            (alpha / aspect, alpha * aspect)
+        } else {
+            (alpha * aspect, alpha / aspect)
+        };
+        Microfacet {
+            distribution,
+            alpha_x,
+            alpha_y,
+        }
+    }
+
+    /// Smith's auxiliary function for the facets hidden from direction `w`.
+    fn lambda(&self, w: Direction) -> f64 {
+        let cos2 = w.z * w.z;
+        let sin2 = (1.0 - cos2).max(0.0);
+        if sin2 == 0.0 {
+            return 0.0;
+        }
+        let alpha2 = (w.x * w.x * self.alpha_x * self.alpha_x + w.y * w.y * self.alpha_y * self.alpha_y) / sin2;
+        let tan2 = sin2 / cos2;
+        match self.distribution {
+            Distribution::Ggx => ((1.0 + alpha2 * tan2).sqrt() - 1.0) * 0.5,
+            Distribution::Beckmann => {
+                // rational approximation from Walter et al.
+                let a = 1.0 / (alpha2 * tan2).sqrt();
+                if a >= 1.6 {
+                    0.0
+                } else {
+                    (1.0 - 1.259 * a + 0.396 * a * a) / (3.535 * a + 2.181 * a * a)
+                }
+            }
+        }
+    }
+
+    /// Fraction of facets visible from both directions, with correlated masking and shadowing.
+    pub fn g2(&self, wo: Direction, wi: Direction) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Picks a facet normal with probability proportional to its density times `m.z`, by sampling
+    /// the facet slopes.
+    pub fn sample_normal(&self) -> Direction {
+        let mut rng = rand::rng();
+        let u1: f64 = rng.random();
+        let phi = 2.0 * PI * rng.random::<f64>();
+        let r = match self.distribution {
+            Distribution::Ggx => (u1 / (1.0 - u1)).sqrt(),
+            Distribution::Beckmann => (-(1.0 - u1).ln()).sqrt(),
+        };
+        Direction::new(-self.alpha_x * r * phi.cos(), -self.alpha_y * r * phi.sin(), 1.0).normalize()
+    }
+
+    /// Throughput of a path reflected or refracted from `wo` to `wi` by facet `m` picked by
+    /// `sample_normal`: the BSDF times the cosine divided by the probability of choosing `wi`, without
+    /// the Fresnel term. Zero when the facet can't be seen from both directions.
+    pub fn scatter_weight(&self, wo: Direction, wi: Direction, m: Direction) -> f64 {
+        if wo.z <= 0.0 || wo.dot(m) <= 0.0 || wi.dot(m) * wi.z <= 0.0 {
+            return 0.0;
+        }
+        self.g2(wo, wi) * wo.dot(m) / (wo.z * m.z)
+    }
+}
+
+/// An orthonormal basis around a surface normal, for moving directions in and out of the local
+/// shading frame.
+#[derive(Debug, Copy, Clone)]
+pub struct ShadingFrame {
+    pub tangent: Direction,
+    pub bitangent: Direction,
+    pub normal: Direction,
+}
+
+impl ShadingFrame {
+    /// The tangent runs around the world y axis, or around the x axis for normals close to y.
+    pub fn new(normal: Direction) -> ShadingFrame {
+        let axis = if normal.y.abs() < 0.999 {
+            Direction::new(0.0, 1.0, 0.0)
+        } else {
+            Direction::new(1.0, 0.0, 0.0)
+        };
+        let tangent = axis.cross(normal).normalize();
+        ShadingFrame {
+            tangent,
+            bitangent: normal.cross(tangent),
+            normal,
+        }
+    }
+
+    pub fn to_local(self, v: Direction) -> Direction {
+        Direction::new(v.dot(self.tangent), v.dot(self.bitangent), v.dot(self.normal))
+    }
+
+    pub fn to_world(self, v: Direction) -> Direction {
+        self.tangent * v.x + self.bitangent * v.y + self.normal * v.z
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    /// Density of facets with normal `m`.
+    fn d(microfacet: &Microfacet, m: Direction) -> f64 {
+        let (alpha_x, alpha_y) = (microfacet.alpha_x, microfacet.alpha_y);
+        if m.z <= 0.0 {
+            return 0.0;
+        }
+        let cos2 = m.z * m.z;
+        let e = ((m.x / alpha_x).powi(2) + (m.y / alpha_y).powi(2)) / cos2;
+        match microfacet.distribution {
+            Distribution::Ggx => 1.0 / (PI * alpha_x * alpha_y * cos2 * cos2 * (1.0 + e).powi(2)),
+            Distribution::Beckmann => (-e).exp() / (PI * alpha_x * alpha_y * cos2 * cos2),
+        }
+    }
+
+    /// Integrates `d(m) * m.z` over the hemisphere, which is 1 for a normalized distribution.
+    fn projected_area(m: &Microfacet) -> f64 {
+        let (n_theta, n_phi) = (400, 200);
+        let mut sum = 0.0;
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) / n_theta as f64 * PI * 0.5;
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) / n_phi as f64 * PI * 2.0;
+                let w = Direction::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+                sum += d(m, w) * w.z * theta.sin();
+            }
+        }
+        sum * (PI * 0.5 / n_theta as f64) * (PI * 2.0 / n_phi as f64)
+    }
+
+    #[test]
+    pub fn distributions_are_normalized() {
+        for distribution in [Distribution::Ggx, Distribution::Beckmann] {
+            for (roughness, anisotropy) in [(0.5, 0.0), (0.7, 0.6)] {
+                let area = projected_area(&Microfacet::new(distribution, roughness, anisotropy));
+                assert!((area - 1.0).abs() < 0.01, "{:?} {}", distribution, area);
+            }
+        }
+    }
+
+    #[test]
+    pub fn sampled_normals_face_up() {
+        let m = Microfacet::new(Distribution::Ggx, 0.8, 0.5);
+        for _ in 0..100 {
+            let n = m.sample_normal();
+            assert!(n.z > 0.0);
+            assert_approx_eq!(n.length(), 1.0);
+        }
+    }
+
+    #[test]
+    pub fn scatter_weight_needs_visible_facet() {
+        let m = Microfacet::new(Distribution::Ggx, 0.5, 0.0);
+        let wo = Direction::new(0.6, 0.0, 0.8);
+        let up = Direction::new(0.0, 0.0, 1.0);
+        // a smooth mirror at normal incidence keeps all the energy
+        assert!((m.scatter_weight(up, up, up) - 1.0).abs() < 1e-9);
+        assert!(m.scatter_weight(wo, Direction::new(-0.6, 0.0, 0.8), up) > 0.0);
+        // leaving through the facet from the wrong side, or off a facet facing away
+        let tilted = Direction::new(0.6, 0.0, 0.8);
+        assert_eq!(m.scatter_weight(wo, Direction::new(0.995, 0.0, -0.1), tilted), 0.0);
+        let away = Direction::new(-0.9, 0.0, 0.436);
+        assert_eq!(m.scatter_weight(wo, up, away), 0.0);
+    }
+
+    #[test]
+    pub fn shading_frame_round_trip() {
+        let f = ShadingFrame::new(Direction::new(1.0, 2.0, -0.5).normalize());
+        let v = Direction::new(0.3, -0.4, 0.8);
+        assert_approx_eq!(f.to_world(f.to_local(v)), v);
+        assert_approx_eq!(f.to_local(f.normal), Direction::new(0.0, 0.0, 1.0));
+    }
+}
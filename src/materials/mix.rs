@@ -0,0 +1,109 @@
+use rand::Rng;
+
+use crate::color::Color;
+use crate::materials::{Material, ScatteredRay};
+use crate::spectrum::Wavelengths;
+use crate::system::{RayHit, RenderContext};
+use crate::texture::Texture;
+
+/// A blend of two materials, such as patches of rust over paint. Each ray scatters off one of the
+/// materials, picked at random with the weight at the hit, while emitted light is blended.
+#[derive(Clone)]
+pub struct Mix {
+    /// How much of the second material there is, from 0 to 1, as the average of the texture's channels.
+    weight: Texture,
+    first: Box<dyn Material>,
+    second: Box<dyn Material>,
+}
+
+impl Mix {
+    pub fn new(weight: Texture, first: Box<dyn Material>, second: Box<dyn Material>) -> Mix {
+        Mix { weight, first, second }
+    }
+
+    fn weight_at(&self, hit: &RayHit) -> f64 {
+        self.weight.value_at_uv(hit.uv).clamp(0.0, 1.0)
+    }
+}
+
+impl Material for Mix {
+    fn scatter(&self, context: &RenderContext, hit: &RayHit) -> Option<ScatteredRay> {
+        if rand::rng().random::<f64>() < self.weight_at(hit) {
+            self.second.scatter(context, hit)
+        } else {
+            self.first.scatter(context, hit)
+        }
+    }
+
+    fn emit(&self, context: &RenderContext, hit: &RayHit) -> Color {
+        let w = self.weight_at(hit);
+        self.first.emit(context, hit) * (1.0 - w) + self.second.emit(context, hit) * w
+    }
+
+    fn emit_spectrum(&self, context: &RenderContext, hit: &RayHit, wavelengths: &Wavelengths) -> Color {
+        let w = self.weight_at(hit);
+        self.first.emit_spectrum(context, hit, wavelengths) * (1.0 - w)
+            + self.second.emit_spectrum(context, hit, wavelengths) * w
+    }
+
+    fn box_clone(&self) -> Box<dyn Material> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Camera, Projection};
+    use crate::direction::Direction;
+    use crate::materials::diffuse_light::DiffuseLight;
+    use crate::matrix::Matrix44f;
+    use crate::object::Object;
+    use crate::point::Point;
+    use crate::sdl;
+    use crate::shapes::Sphere;
+    use crate::system::{Intersection, Ray};
+    use crate::test_utils::*;
+    use crate::vector::Vector2f;
+
+    fn context() -> RenderContext {
+        let camera = Camera::new(8.0, 8.0, Projection::Perspective(40.0), Matrix44f::identity());
+        RenderContext::new(test_options(), sdl::new_scene(None, camera, Vec::new()))
+    }
+
+    fn red_and_blue(weight: f64) -> Mix {
+        Mix::new(
+            Texture::Solid(Color::new(weight, weight, weight)),
+            Box::new(DiffuseLight::new(1.0, Texture::Solid(Color::new(1.0, 0.0, 0.0)))),
+            Box::new(DiffuseLight::new(1.0, Texture::Solid(Color::new(0.0, 0.0, 1.0)))),
+        )
+    }
+
+    #[test]
+    pub fn emit_blends_by_clamped_weight() {
+        let context = context();
+        let object = Object::new(
+            "light",
+            Box::new(Sphere::new(Point::new(0.0, 0.0, -3.0), 1.0)),
+            Box::new(red_and_blue(0.5)),
+        );
+        let ray = Ray::primary(Point::zero(), Direction::new(0.0, 0.0, -1.0), 0);
+        let hit = RayHit::new(
+            &ray,
+            &object,
+            Intersection {
+                t: 2.0,
+                n: Direction::new(0.0, 0.0, 1.0),
+                uv: Vector2f(0.5, 0.5),
+            },
+        );
+
+        assert_approx_eq!(red_and_blue(-0.5).weight_at(&hit), 0.0);
+        assert_approx_eq!(red_and_blue(0.25).weight_at(&hit), 0.25);
+        assert_approx_eq!(red_and_blue(1.5).weight_at(&hit), 1.0);
+
+        assert_approx_eq!(red_and_blue(0.25).emit(&context, &hit), Color::new(0.75, 0.0, 0.25));
+        assert_approx_eq!(red_and_blue(-0.5).emit(&context, &hit), Color::new(1.0, 0.0, 0.0));
+        assert_approx_eq!(red_and_blue(1.5).emit(&context, &hit), Color::new(0.0, 0.0, 1.0));
+    }
+}
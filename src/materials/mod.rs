@@ -1,11 +1,16 @@
 use crate::color::Color;
 use crate::direction::Direction;
 use crate::point::Point;
+use crate::spectrum::Wavelengths;
 use crate::system::{RayHit, RenderContext};
 
 pub trait Material: Send + Sync {
     fn scatter(&self, context: &RenderContext, hit: &RayHit) -> Option<ScatteredRay>;
     fn emit(&self, context: &RenderContext, hit: &RayHit) -> Color;
+    /// Emitted light at each of the wavelengths in spectral rendering.
+    fn emit_spectrum(&self, context: &RenderContext, hit: &RayHit, wavelengths: &Wavelengths) -> Color {
+        wavelengths.upsample(self.emit(context, hit))
+    }
     fn box_clone(&self) -> Box<dyn Material>;
 }
 
@@ -15,20 +20,47 @@ impl Clone for Box<dyn Material> {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScatterKind {
+    Diffuse,
+    Specular,
+    Volume,
+    /// A step of a random walk inside a material, which doesn't count towards the depth of the path.
+    Subsurface,
+}
+
 pub struct ScatteredRay {
+    pub kind: ScatterKind,
     pub attenuation: Color,
     pub origin: Point,
     pub direction: Direction,
+    /// Wavelength in nanometres that the scattered light is limited to, if any.
+    pub wavelength: Option<f64>,
 }
 
+mod coated;
+mod conductor;
 mod dielectric;
 mod diffuse_light;
 mod isotropic;
 mod lambertian;
 mod metal;
+mod microfacet;
+mod mix;
+mod oren_nayar;
+mod principled;
+mod subsurface;
+
+pub use self::coated::Coated;
+pub use self::conductor::{Conductor, ConductorPreset};
 
-pub use self::dielectric::Dielectric;
+pub use self::dielectric::{Absorption, Dielectric, Ior, refract};
 pub use self::diffuse_light::DiffuseLight;
 pub use self::isotropic::Isotropic;
 pub use self::lambertian::Lambertian;
 pub use self::metal::Metal;
+pub use self::microfacet::{Distribution, Microfacet};
+pub use self::mix::Mix;
+pub use self::oren_nayar::OrenNayar;
+pub use self::principled::Principled;
+pub use self::subsurface::Subsurface;
@@ -0,0 +1,102 @@
+use crate::color::Color;
+use crate::direction::{Direction, Dot};
+use crate::materials::Material;
+use crate::materials::{ScatterKind, ScatteredRay};
+use crate::system::{RayHit, RenderContext};
+use crate::texture::{ColorSource, Texture};
+
+/// A rough diffuse surface, which looks flatter and brighter towards the viewer than a Lambertian one.
+/// Uses the qualitative model from Oren and Nayar, "Generalization of Lambert's Reflectance Model".
+#[derive(Clone)]
+pub struct OrenNayar {
+    a: f64,
+    b: f64,
+    texture: Texture,
+}
+
+impl OrenNayar {
+    /// `sigma` is the standard deviation of the facet angles in degrees; zero is Lambertian.
+    pub fn new(sigma: f64, texture: Texture) -> OrenNayar {
+        let sigma2 = sigma.to_radians().powi(2);
+        OrenNayar {
+            a: 1.0 - 0.5 * sigma2 / (sigma2 + 0.33),
+            b: 0.45 * sigma2 / (sigma2 + 0.09),
+            texture,
+        }
+    }
+
+    /// Reflectance relative to a Lambertian surface, for light arriving from `wi` and leaving towards
+    /// `wo`.
+    fn factor(&self, n: Direction, wi: Direction, wo: Direction) -> f64 {
+        let cos_i = wi.dot(n).clamp(0.0, 1.0);
+        let cos_o = wo.dot(n).clamp(0.0, 1.0);
+        let sin_i = (1.0 - cos_i * cos_i).sqrt();
+        let sin_o = (1.0 - cos_o * cos_o).sqrt();
+        // cosine of the azimuth between the directions, in the tangent plane
+        let cos_phi = if sin_i > 1e-6 && sin_o > 1e-6 {
+            ((wi - n * cos_i).dot(wo - n * cos_o) / (sin_i * sin_o)).max(0.0)
+        } else {
+            0.0
+        };
+        // sin(alpha) * tan(beta), where alpha is the larger angle from the normal and beta the smaller
+        let (sin_alpha, tan_beta) = if cos_i < cos_o {
+            (sin_i, sin_o / cos_o.max(1e-6))
+        } else {
+            (sin_o, sin_i / cos_i.max(1e-6))
+        };
+        self.a + self.b * cos_phi * sin_alpha * tan_beta
+    }
+}
+
+impl Material for OrenNayar {
+    fn scatter(&self, context: &RenderContext, hit: &RayHit) -> Option<ScatteredRay> {
+        let n = if hit.incident.direction.dot(hit.n) > 0.0 {
+            -hit.n
+        } else {
+            hit.n
+        };
+        let scattered_dir = (n + Direction::uniform_sphere_distribution()).normalize();
+        let factor = self.factor(n, scattered_dir, -hit.incident.direction);
+
+        Some(ScatteredRay {
+            kind: ScatterKind::Diffuse,
+            attenuation: self.texture.color_at_uv(hit.uv) * factor,
+            origin: hit.point() + n * context.options.bias,
+            direction: scattered_dir,
+            wavelength: hit.incident.wavelength,
+        })
+    }
+
+    fn emit(&self, _context: &RenderContext, _hit: &RayHit) -> Color {
+        Color::black()
+    }
+
+    fn box_clone(&self) -> Box<dyn Material> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    const N: Direction = Direction { x: 0.0, y: 1.0, z: 0.0 };
+
+    #[test]
+    pub fn zero_sigma_is_lambertian() {
+        let m = OrenNayar::new(0.0, Texture::Solid(Color::white()));
+        let wi = Direction::new(0.6, 0.8, 0.0);
+        let wo = Direction::new(-0.8, 0.6, 0.0);
+        assert_approx_eq!(m.factor(N, wi, wo), 1.0);
+    }
+
+    #[test]
+    pub fn rough_surface_is_brighter_towards_light() {
+        let m = OrenNayar::new(30.0, Texture::Solid(Color::white()));
+        let wi = Direction::new(0.8, 0.6, 0.0);
+        // looking back along the light versus from the opposite side
+        assert!(m.factor(N, wi, wi) > m.factor(N, wi, Direction::new(-0.8, 0.6, 0.0)));
+        assert!(m.factor(N, N, N) < 1.0);
+    }
+}
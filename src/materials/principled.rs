@@ -0,0 +1,213 @@
+use std::f64::consts::PI;
+
+use rand::Rng;
+
+use crate::color::Color;
+use crate::direction::{Direction, Dot};
+use crate::materials::dielectric::{Dielectric, Ior};
+use crate::materials::microfacet::{Distribution, Microfacet, ShadingFrame};
+use crate::materials::{Material, ScatterKind, ScatteredRay};
+use crate::system::{RayHit, RenderContext};
+use crate::texture::{ColorSource, Texture};
+
+/// An all-purpose material in the style of the Disney principled BSDF, mixing a diffuse base with
+/// sheen, a specular layer that turns into a metal, a clear coat and glass-like transmission. Every
+/// parameter is a texture, with scalar parameters taken from the average of its channels.
+#[derive(Clone)]
+pub struct Principled {
+    pub base_color: Texture,
+    pub metallic: Texture,
+    pub roughness: Texture,
+    /// Strength of the specular reflection of the non-metallic part, 0.5 being typical of most
+    /// materials.
+    pub specular: Texture,
+    /// How much the specular reflection takes on the hue of the base colour.
+    pub specular_tint: Texture,
+    /// Extra reflection at grazing angles, for cloth.
+    pub sheen: Texture,
+    pub sheen_tint: Texture,
+    pub clearcoat: Texture,
+    pub clearcoat_roughness: Texture,
+    pub transmission: Texture,
+    pub ior: Texture,
+}
+
+fn constant(v: f64) -> Texture {
+    Texture::Solid(Color::new(v, v, v))
+}
+
+impl Principled {
+    pub fn new(base_color: Texture) -> Principled {
+        Principled {
+            base_color,
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            specular_tint: constant(0.0),
+            sheen: constant(0.0),
+            sheen_tint: constant(0.5),
+            clearcoat: constant(0.0),
+            clearcoat_roughness: constant(0.03),
+            transmission: constant(0.0),
+            ior: constant(1.5),
+        }
+    }
+
+    /// Disney's retro-reflective diffuse with sheen, sampled by cosine.
+    fn sample_diffuse(&self, context: &RenderContext, hit: &RayHit, n: Direction) -> ScatteredRay {
+        let base = self.base_color.color_at_uv(hit.uv);
+        let roughness = self.roughness.value_at_uv(hit.uv);
+        let sheen = self.sheen.value_at_uv(hit.uv);
+        let sheen_color = mix(Color::white(), tint(base), self.sheen_tint.value_at_uv(hit.uv));
+
+        let wo = -hit.incident.direction;
+        let wi = (n + Direction::uniform_sphere_distribution()).normalize();
+        let cos_d = wi.dot((wo + wi).normalize());
+        let fd90 = 0.5 + 2.0 * roughness * cos_d * cos_d;
+        let fl = 1.0 + (fd90 - 1.0) * schlick_weight(wi.dot(n));
+        let fv = 1.0 + (fd90 - 1.0) * schlick_weight(wo.dot(n));
+        ScatteredRay {
+            kind: ScatterKind::Diffuse,
+            attenuation: base * (fl * fv) + sheen_color * (PI * sheen * schlick_weight(cos_d)),
+            origin: hit.point() + n * context.options.bias,
+            direction: wi,
+            wavelength: hit.incident.wavelength,
+        }
+    }
+
+    /// Reflection off a GGX microfacet layer with a Schlick Fresnel term.
+    fn sample_reflection(
+        &self,
+        context: &RenderContext,
+        hit: &RayHit,
+        n: Direction,
+        roughness: f64,
+        f0: Color,
+    ) -> Option<ScatteredRay> {
+        let microfacet = Microfacet::new(Distribution::Ggx, roughness, 0.0);
+        let frame = ShadingFrame::new(n);
+        let wo = frame.to_local(-hit.incident.direction);
+        let m = microfacet.sample_normal();
+        let wi = (-wo).reflect(m);
+        let weight = microfacet.scatter_weight(wo, wi, m);
+        if weight == 0.0 {
+            return None;
+        }
+        let fresnel = mix(f0, Color::white(), schlick_weight(wo.dot(m)));
+        Some(ScatteredRay {
+            kind: ScatterKind::Specular,
+            attenuation: fresnel * weight,
+            origin: hit.point() + n * context.options.bias,
+            direction: frame.to_world(wi).normalize(),
+            wavelength: hit.incident.wavelength,
+        })
+    }
+
+    /// Rough glass, tinted by the base colour each time light passes through the surface.
+    fn sample_transmission(&self, context: &RenderContext, hit: &RayHit) -> Option<ScatteredRay> {
+        let roughness = self.roughness.value_at_uv(hit.uv);
+        let glass = Dielectric::new(
+            Ior::Constant(self.ior.value_at_uv(hit.uv)),
+            Some(Microfacet::new(Distribution::Ggx, roughness, 0.0)),
+            None,
+        );
+        let mut scattered = glass.scatter(context, hit)?;
+        let refracted = scattered.direction.dot(hit.n) * hit.incident.direction.dot(hit.n) > 0.0;
+        if refracted {
+            let base = self.base_color.color_at_uv(hit.uv);
+            scattered.attenuation = scattered.attenuation * Color::new(base.r.sqrt(), base.g.sqrt(), base.b.sqrt());
+        }
+        Some(scattered)
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, context: &RenderContext, hit: &RayHit) -> Option<ScatteredRay> {
+        let uv = hit.uv;
+        let metallic = self.metallic.value_at_uv(uv).clamp(0.0, 1.0);
+        let transmission = self.transmission.value_at_uv(uv).clamp(0.0, 1.0);
+        let clearcoat = self.clearcoat.value_at_uv(uv).max(0.0);
+
+        // the lobes are picked in proportion to their weights, so each is scaled by the total
+        let lobes = [
+            (1.0 - metallic) * (1.0 - transmission),
+            1.0 - (1.0 - metallic) * transmission,
+            (1.0 - metallic) * transmission,
+            0.25 * clearcoat,
+        ];
+        let total: f64 = lobes.iter().sum();
+        let mut pick = rand::rng().random::<f64>() * total;
+        let lobe = lobes.iter().position(|w| {
+            pick -= w;
+            pick < 0.0
+        });
+
+        // opaque lobes reflect on the side the ray arrives from
+        let n = if hit.incident.direction.dot(hit.n) > 0.0 {
+            -hit.n
+        } else {
+            hit.n
+        };
+        let mut scattered = match lobe {
+            Some(0) => self.sample_diffuse(context, hit, n),
+            Some(1) => {
+                let base = self.base_color.color_at_uv(uv);
+                let specular = self.specular.value_at_uv(uv);
+                let dielectric =
+                    mix(Color::white(), tint(base), self.specular_tint.value_at_uv(uv)) * (0.08 * specular);
+                let f0 = mix(dielectric, base, metallic);
+                self.sample_reflection(context, hit, n, self.roughness.value_at_uv(uv), f0)?
+            }
+            Some(2) => self.sample_transmission(context, hit)?,
+            _ => {
+                let roughness = self.clearcoat_roughness.value_at_uv(uv);
+                self.sample_reflection(context, hit, n, roughness, Color::new(0.04, 0.04, 0.04))?
+            }
+        };
+        scattered.attenuation = scattered.attenuation * total;
+        Some(scattered)
+    }
+
+    fn emit(&self, _context: &RenderContext, _hit: &RayHit) -> Color {
+        Color::black()
+    }
+
+    fn box_clone(&self) -> Box<dyn Material> {
+        Box::new(self.clone())
+    }
+}
+
+/// `(1 - cos)^5`, the angular falloff of Schlick's Fresnel approximation.
+fn schlick_weight(cos: f64) -> f64 {
+    (1.0 - cos.clamp(0.0, 1.0)).powi(5)
+}
+
+/// The hue of a colour, with its luminance normalized to 1.
+fn tint(c: Color) -> Color {
+    let luminance = 0.3 * c.r + 0.6 * c.g + 0.1 * c.b;
+    if luminance > 0.0 { c / luminance } else { Color::white() }
+}
+
+fn mix(a: Color, b: Color, v: f64) -> Color {
+    a * (1.0 - v) + b * v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    pub fn tint_normalizes_luminance() {
+        let t = tint(Color::new(0.2, 0.4, 0.8));
+        assert_approx_eq!(0.3 * t.r + 0.6 * t.g + 0.1 * t.b, 1.0);
+        assert_approx_eq!(tint(Color::black()), Color::white());
+    }
+
+    #[test]
+    pub fn schlick_weight_range() {
+        assert_approx_eq!(schlick_weight(1.0), 0.0);
+        assert_approx_eq!(schlick_weight(0.0), 1.0);
+        assert_approx_eq!(schlick_weight(-0.5), 1.0);
+    }
+}
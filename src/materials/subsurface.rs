@@ -0,0 +1,165 @@
+use rand::Rng;
+
+use crate::color::Color;
+use crate::direction::{Direction, Dot};
+use crate::materials::dielectric::{fresnel, refract};
+use crate::materials::{Material, ScatterKind, ScatteredRay};
+use crate::spectrum;
+use crate::system::{RayHit, RenderContext};
+use crate::texture::{ColorSource, Texture};
+
+/// Skin, marble, wax and other translucent materials, where light enters a smooth surface and takes
+/// a random walk through the medium inside before it leaves. Free flights inside are sampled like in
+/// `HomogenousMedium`, each time the walk reaches the boundary of the object. Steps inside the medium
+/// don't count towards the depth of the path. A mean free path that differs between colours limits
+/// each walk to a single wavelength.
+#[derive(Clone)]
+pub struct Subsurface {
+    ior: f64,
+    /// Overall colour of the material after light has scattered many times inside it.
+    color: Texture,
+    /// Average distance light travels between scattering events, for red, green and blue.
+    mean_free_path: Color,
+}
+
+impl Subsurface {
+    pub fn new(ior: f64, color: Texture, mean_free_path: Color) -> Subsurface {
+        Subsurface {
+            ior,
+            color,
+            mean_free_path,
+        }
+    }
+
+    /// Average distance between scattering events for light of the given wavelength, or of any
+    /// colour when the mean free path is the same for all of them.
+    fn mean_free_path_at(&self, wavelength: Option<f64>) -> f64 {
+        match wavelength {
+            Some(wavelength) => spectrum::rgb_band_value(self.mean_free_path, wavelength),
+            None => self.mean_free_path.r,
+        }
+    }
+
+    fn is_chromatic(&self) -> bool {
+        let m = self.mean_free_path;
+        m.r != m.g || m.r != m.b
+    }
+}
+
+/// Albedo of a single scattering event that gives the multiple scattering albedo `a` seen on the
+/// surface, from Chiang et al., "Practical and Controllable Subsurface Scattering for Production Path
+/// Tracing".
+fn single_scattering_albedo(a: f64) -> f64 {
+    let a = a.clamp(0.0, 1.0);
+    1.0 - (4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt()).powi(2)
+}
+
+impl Material for Subsurface {
+    fn scatter(&self, context: &RenderContext, hit: &RayHit) -> Option<ScatteredRay> {
+        let p = hit.point();
+        let incident = hit.incident.direction;
+        let bias = hit.n * context.options.bias;
+        let mut rng = rand::rng();
+
+        if incident.dot(hit.n) < 0.0 {
+            // light reflects off the surface or refracts into the medium
+            if rng.random::<f64>() < fresnel(incident, hit.n, self.ior) {
+                return Some(ScatteredRay {
+                    kind: ScatterKind::Specular,
+                    attenuation: Color::white(),
+                    origin: p + bias,
+                    direction: incident.reflect(hit.n).normalize(),
+                    wavelength: hit.incident.wavelength,
+                });
+            }
+            // when colours travel different distances, the walk is limited to a single wavelength, as
+            // through a dispersive dielectric
+            let (wavelength, tint) = spectrum::path_wavelength(self.is_chromatic(), hit.incident);
+            return Some(ScatteredRay {
+                kind: ScatterKind::Volume,
+                attenuation: tint,
+                origin: p - bias,
+                direction: refract(incident, hit.n, self.ior).normalize(),
+                wavelength,
+            });
+        }
+
+        // the ray has travelled through the medium from where it entered or last scattered
+        let distance = (p - hit.incident.origin).length();
+        let wavelength = hit.incident.wavelength;
+        let scatter_distance = -self.mean_free_path_at(wavelength) * rng.random::<f64>().ln();
+        if scatter_distance < distance {
+            let c = self.color.color_at_uv(hit.uv);
+            let albedo = match wavelength {
+                Some(wavelength) => {
+                    let a = single_scattering_albedo(spectrum::rgb_to_spectrum(c, wavelength));
+                    Color::new(a, a, a)
+                }
+                None => Color::new(
+                    single_scattering_albedo(c.r),
+                    single_scattering_albedo(c.g),
+                    single_scattering_albedo(c.b),
+                ),
+            };
+            Some(ScatteredRay {
+                kind: ScatterKind::Subsurface,
+                attenuation: albedo,
+                origin: hit.incident.origin + incident * scatter_distance,
+                direction: Direction::uniform_sphere_distribution(),
+                wavelength,
+            })
+        } else {
+            // leaving the medium, unless reflected back inside
+            let reflect = rng.random::<f64>() < fresnel(incident, hit.n, self.ior);
+            Some(ScatteredRay {
+                kind: if reflect {
+                    ScatterKind::Subsurface
+                } else {
+                    ScatterKind::Volume
+                },
+                attenuation: Color::white(),
+                origin: if reflect { p - bias } else { p + bias },
+                direction: if reflect {
+                    incident.reflect(hit.n).normalize()
+                } else {
+                    refract(incident, hit.n, self.ior).normalize()
+                },
+                wavelength,
+            })
+        }
+    }
+
+    fn emit(&self, _context: &RenderContext, _hit: &RayHit) -> Color {
+        Color::black()
+    }
+
+    fn box_clone(&self) -> Box<dyn Material> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    pub fn single_scattering_albedo_range() {
+        assert!(single_scattering_albedo(0.0).abs() < 1e-4);
+        assert!(single_scattering_albedo(1.0) > 0.998);
+        // scattering many times darkens the surface, so each event must lose less light
+        assert!(single_scattering_albedo(0.5) > 0.5);
+        assert!(single_scattering_albedo(0.8) > single_scattering_albedo(0.5));
+    }
+
+    #[test]
+    pub fn mean_free_path_by_wavelength() {
+        let skin = Subsurface::new(1.4, Texture::Solid(Color::white()), Color::new(0.4, 0.15, 0.08));
+        assert!(skin.is_chromatic());
+        assert_approx_eq!(skin.mean_free_path_at(Some(650.0)), 0.4);
+        assert_approx_eq!(skin.mean_free_path_at(Some(450.0)), 0.08);
+        let marble = Subsurface::new(1.5, Texture::Solid(Color::white()), Color::new(0.05, 0.05, 0.05));
+        assert!(!marble.is_chromatic());
+        assert_approx_eq!(marble.mean_free_path_at(None), 0.05);
+    }
+}
@@ -0,0 +1,340 @@
+use std::f64;
+
+use crate::direction::{Direction, Dot};
+use crate::matrix::Matrix44f;
+use crate::object::Transformation;
+
+/// Rotation as a unit quaternion, for interpolating orientations without distorting them.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Quaternion {
+    pub w: f64,
+    pub v: Direction,
+}
+
+impl Quaternion {
+    /// Extracts the rotation from a matrix that is a pure rotation (no scale, shear or translation).
+    pub fn from_rotation(m: Matrix44f) -> Quaternion {
+        // the matrix transforms row vectors, so it is the transpose of the usual column form
+        let c = |i: usize, j: usize| m[j][i];
+        let trace = c(0, 0) + c(1, 1) + c(2, 2);
+        let (w, x, y, z) = if trace > 0.0 {
+            let s = (trace + 1.0).sqrt() * 2.0;
+            (
+                0.25 * s,
+                (c(2, 1) - c(1, 2)) / s,
+                (c(0, 2) - c(2, 0)) / s,
+                (c(1, 0) - c(0, 1)) / s,
+            )
+        } else if c(0, 0) > c(1, 1) && c(0, 0) > c(2, 2) {
+            let s = (1.0 + c(0, 0) - c(1, 1) - c(2, 2)).sqrt() * 2.0;
+            (
+                (c(2, 1) - c(1, 2)) / s,
+                0.25 * s,
+                (c(0, 1) + c(1, 0)) / s,
+                (c(0, 2) + c(2, 0)) / s,
+            )
+        } else if c(1, 1) > c(2, 2) {
+            let s = (1.0 + c(1, 1) - c(0, 0) - c(2, 2)).sqrt() * 2.0;
+            (
+                (c(0, 2) - c(2, 0)) / s,
+                (c(0, 1) + c(1, 0)) / s,
+                0.25 * s,
+                (c(1, 2) + c(2, 1)) / s,
+            )
+        } else {
+            let s = (1.0 + c(2, 2) - c(0, 0) - c(1, 1)).sqrt() * 2.0;
+            (
+                (c(1, 0) - c(0, 1)) / s,
+                (c(0, 2) + c(2, 0)) / s,
+                (c(1, 2) + c(2, 1)) / s,
+                0.25 * s,
+            )
+        };
+        Quaternion {
+            w,
+            v: Direction::new(x, y, z),
+        }
+        .normalize()
+    }
+
+    pub fn to_matrix(self) -> Matrix44f {
+        let (w, x, y, z) = (self.w, self.v.x, self.v.y, self.v.z);
+        Matrix44f([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y + w * z),
+                2.0 * (x * z - w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y - w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z + w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z + w * y),
+                2.0 * (y * z - w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn dot(&self, other: &Quaternion) -> f64 {
+        self.w * other.w + self.v.dot(other.v)
+    }
+
+    pub fn normalize(&self) -> Quaternion {
+        let len = self.dot(self).sqrt();
+        Quaternion {
+            w: self.w / len,
+            v: self.v / len,
+        }
+    }
+
+    /// Spherical linear interpolation along the shorter arc between two rotations.
+    pub fn slerp(&self, other: &Quaternion, t: f64) -> Quaternion {
+        let mut cos_theta = self.dot(other);
+        let mut other = *other;
+        if cos_theta < 0.0 {
+            other = Quaternion {
+                w: -other.w,
+                v: -other.v,
+            };
+            cos_theta = -cos_theta;
+        }
+        if cos_theta > 0.9995 {
+            // nearly the same rotation, where slerp becomes unstable
+            return Quaternion {
+                w: self.w + (other.w - self.w) * t,
+                v: self.v + (other.v - self.v) * t,
+            }
+            .normalize();
+        }
+        let theta = cos_theta.acos();
+        let a = ((1.0 - t) * theta).sin() / theta.sin();
+        let b = (t * theta).sin() / theta.sin();
+        Quaternion {
+            w: self.w * a + other.w * b,
+            v: self.v * a + other.v * b,
+        }
+    }
+}
+
+/// A transform split into scale, rotation and translation, applied in that order.
+#[derive(Debug, Copy, Clone)]
+pub struct DecomposedTransform {
+    pub scale: Matrix44f,
+    pub rotation: Quaternion,
+    pub translation: Direction,
+}
+
+const MAX_POLAR_ITERATIONS: u32 = 100;
+
+impl DecomposedTransform {
+    /// Decomposes a transform made of translations, rotations and scales. The rotation is found by
+    /// polar decomposition, so any shear from non-uniform scaling ends up in the scale matrix.
+    pub fn new(m: Matrix44f) -> DecomposedTransform {
+        let translation = Direction::new(m[3][0], m[3][1], m[3][2]);
+        let mut linear = m;
+        linear[3] = [0.0, 0.0, 0.0, 1.0];
+
+        let mut r = linear;
+        for _ in 0..MAX_POLAR_ITERATIONS {
+            let r_inv_t = r.inverse().transpose();
+            let mut next = Matrix44f::zero();
+            let mut change: f64 = 0.0;
+            for i in 0..3 {
+                for j in 0..3 {
+                    next[i][j] = 0.5 * (r[i][j] + r_inv_t[i][j]);
+                    change = change.max((next[i][j] - r[i][j]).abs());
+                }
+            }
+            next[3][3] = 1.0;
+            r = next;
+            if change < 1e-10 {
+                break;
+            }
+        }
+
+        DecomposedTransform {
+            // row vectors are scaled before being rotated: linear = scale * r
+            scale: linear * r.transpose(),
+            rotation: Quaternion::from_rotation(r),
+            translation,
+        }
+    }
+
+    pub fn to_matrix(self) -> Matrix44f {
+        self.scale * self.rotation.to_matrix() * Matrix44f::translation(self.translation)
+    }
+
+    /// The inverse of `to_matrix`, given the inverse of the scale, undoing the translation, rotation
+    /// and scale in turn.
+    fn inverse_matrix(self, scale_inverse: Matrix44f) -> Matrix44f {
+        Matrix44f::translation(-self.translation) * self.rotation.to_matrix().transpose() * scale_inverse
+    }
+
+    pub fn interpolate(&self, other: &DecomposedTransform, t: f64) -> DecomposedTransform {
+        let mut scale = Matrix44f::zero();
+        for i in 0..4 {
+            for j in 0..4 {
+                scale[i][j] = self.scale[i][j] + (other.scale[i][j] - self.scale[i][j]) * t;
+            }
+        }
+        DecomposedTransform {
+            scale,
+            rotation: self.rotation.slerp(&other.rotation, t),
+            translation: self.translation + (other.translation - self.translation) * t,
+        }
+    }
+}
+
+/// An object's transform at a number of moments, moving between them for motion blur. The transform
+/// holds at the first and last keyframes outside of their range.
+#[derive(Debug, Clone)]
+pub struct Motion {
+    keyframes: Vec<Keyframe>,
+}
+
+/// A keyframe, with the inverse of its scale and its whole transform worked out up front, as the
+/// transform is needed for every intersection test.
+#[derive(Debug, Clone)]
+struct Keyframe {
+    time: f64,
+    transform: DecomposedTransform,
+    scale_inverse: Matrix44f,
+    tx: Transformation,
+}
+
+impl Motion {
+    pub fn new(mut keyframes: Vec<(f64, Matrix44f)>) -> Motion {
+        keyframes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Motion {
+            keyframes: keyframes
+                .into_iter()
+                .map(|(time, m)| {
+                    let transform = DecomposedTransform::new(m);
+                    let scale_inverse = transform.scale.inverse();
+                    Keyframe {
+                        time,
+                        transform,
+                        scale_inverse,
+                        tx: Transformation {
+                            object_to_world: transform.to_matrix(),
+                            world_to_object: transform.inverse_matrix(scale_inverse),
+                        },
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    pub fn transformation_at(&self, time: f64) -> Transformation {
+        let i = self.keyframes.partition_point(|k| k.time <= time);
+        if i == 0 {
+            return self.keyframes[0].tx.clone();
+        }
+        if i == self.keyframes.len() {
+            return self.keyframes[i - 1].tx.clone();
+        }
+        let (a, b) = (&self.keyframes[i - 1], &self.keyframes[i]);
+        let transform = a
+            .transform
+            .interpolate(&b.transform, (time - a.time) / (b.time - a.time));
+        // objects that only move or turn keep the same scale, whose inverse is already known
+        let scale_inverse = if a.transform.scale == b.transform.scale {
+            a.scale_inverse
+        } else {
+            inverse_linear(transform.scale)
+        };
+        Transformation {
+            object_to_world: transform.to_matrix(),
+            world_to_object: transform.inverse_matrix(scale_inverse),
+        }
+    }
+}
+
+/// Inverse of a matrix without translation or projection, from the adjugate of its 3x3 part.
+fn inverse_linear(m: Matrix44f) -> Matrix44f {
+    let c = |i: usize, j: usize| {
+        let (i0, i1) = ((i + 1) % 3, (i + 2) % 3);
+        let (j0, j1) = ((j + 1) % 3, (j + 2) % 3);
+        m[i0][j0] * m[i1][j1] - m[i0][j1] * m[i1][j0]
+    };
+    let det = m[0][0] * c(0, 0) + m[0][1] * c(0, 1) + m[0][2] * c(0, 2);
+    let mut inverse = Matrix44f::identity();
+    for i in 0..3 {
+        for j in 0..3 {
+            inverse[i][j] = c(j, i) / det;
+        }
+    }
+    inverse
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::point::Point;
+    use crate::test_utils::*;
+
+    #[test]
+    pub fn quaternion_round_trip() {
+        for m in [
+            Matrix44f::rotation_x(30.0),
+            Matrix44f::rotation_y(-120.0),
+            Matrix44f::rotation_z(179.0),
+            Matrix44f::rotation_x(45.0) * Matrix44f::rotation_y(60.0),
+        ] {
+            assert_approx_eq!(Quaternion::from_rotation(m).to_matrix(), m);
+        }
+    }
+
+    #[test]
+    pub fn decompose_round_trip() {
+        let m = Matrix44f::scaling(Direction::new(2.0, 3.0, 0.5))
+            * Matrix44f::rotation_y(40.0)
+            * Matrix44f::translation(Direction::new(1.0, -2.0, 3.0));
+        assert_approx_eq!(DecomposedTransform::new(m).to_matrix(), m);
+    }
+
+    #[test]
+    pub fn interpolates_rotation_and_translation() {
+        let motion = Motion::new(vec![
+            (0.0, Matrix44f::identity()),
+            (
+                1.0,
+                Matrix44f::rotation_y(90.0) * Matrix44f::translation(Direction::new(0.0, 2.0, 0.0)),
+            ),
+        ]);
+        let halfway = Matrix44f::rotation_y(45.0) * Matrix44f::translation(Direction::new(0.0, 1.0, 0.0));
+        assert_approx_eq!(motion.transformation_at(0.5).object_to_world, halfway);
+        assert_approx_eq!(
+            Point::new(1.0, 0.0, 0.0) * motion.transformation_at(2.0).object_to_world,
+            Point::new(0.0, 2.0, -1.0)
+        );
+    }
+
+    #[test]
+    pub fn interpolated_transforms_have_inverses() {
+        let motion = Motion::new(vec![
+            (0.0, Matrix44f::translation(Direction::new(1.0, 0.0, 0.0))),
+            (
+                1.0,
+                Matrix44f::rotation_x(60.0) * Matrix44f::translation(Direction::new(0.0, 2.0, 0.0)),
+            ),
+            (
+                2.0,
+                Matrix44f::scaling(Direction::new(2.0, 1.0, 0.5))
+                    * Matrix44f::rotation_z(30.0)
+                    * Matrix44f::translation(Direction::new(0.0, 0.0, -3.0)),
+            ),
+        ]);
+        for time in [-1.0, 0.0, 0.3, 1.0, 1.7, 2.5] {
+            let tx = motion.transformation_at(time);
+            assert_approx_eq!(tx.world_to_object, tx.object_to_world.inverse());
+        }
+    }
+}
@@ -1,9 +1,10 @@
 use crate::materials::Material;
 use crate::matrix::Matrix44f;
+use crate::motion::Motion;
 use crate::shapes::Shape;
 use crate::system::{Intersectable, Intersection, Ray, Transformable};
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct Transformation {
     pub object_to_world: Matrix44f,
     pub world_to_object: Matrix44f,
@@ -29,6 +30,10 @@ pub struct Object {
     pub name: String,
     pub shape: Box<dyn Shape>,
     pub material: Box<dyn Material>,
+    pub light_group: Option<String>,
+    /// Position of the light group among the scene's light groups, set when the scene is built.
+    pub light_group_index: Option<usize>,
+    pub motion: Option<Motion>,
 }
 
 impl Object {
@@ -37,6 +42,9 @@ impl Object {
             name: String::from(name),
             shape,
             material,
+            light_group: None,
+            light_group_index: None,
+            motion: None,
         }
     }
 }
@@ -49,6 +57,16 @@ impl Transformable for Object {
 
 impl Intersectable for Object {
     fn intersect(&self, ray: &Ray) -> Option<Intersection> {
-        self.shape.intersect(ray).filter(|i| i.t >= 0.0)
+        match &self.motion {
+            Some(motion) => {
+                let tx = motion.transformation_at(ray.time);
+                let object_ray = ray.to_object(&tx);
+                self.shape
+                    .intersect(&object_ray)
+                    .map(|i| i.to_world(ray, &object_ray, &tx))
+                    .filter(|i| i.t >= 0.0)
+            }
+            None => self.shape.intersect(ray).filter(|i| i.t >= 0.0),
+        }
     }
 }
@@ -0,0 +1,135 @@
+use crate::color::Color;
+use crate::materials::ScatterKind;
+
+pub const NUM_PASSES: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pass {
+    Emission,
+    DiffuseDirect,
+    DiffuseIndirect,
+    SpecularDirect,
+    SpecularIndirect,
+    Volume,
+}
+
+impl Pass {
+    pub fn all() -> [Pass; NUM_PASSES] {
+        [
+            Pass::Emission,
+            Pass::DiffuseDirect,
+            Pass::DiffuseIndirect,
+            Pass::SpecularDirect,
+            Pass::SpecularIndirect,
+            Pass::Volume,
+        ]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Emission => "emission",
+            Pass::DiffuseDirect => "diffuse_direct",
+            Pass::DiffuseIndirect => "diffuse_indirect",
+            Pass::SpecularDirect => "specular_direct",
+            Pass::SpecularIndirect => "specular_indirect",
+            Pass::Volume => "volume",
+        }
+    }
+
+    /// Classifies light arriving at the camera by the first scattering event along its path and the
+    /// number of bounces between the camera and the vertex where the light was emitted.
+    pub fn classify(event: Option<ScatterKind>, bounce: u16) -> Pass {
+        match (event, bounce) {
+            (None, _) => Pass::Emission,
+            (Some(ScatterKind::Volume | ScatterKind::Subsurface), _) => Pass::Volume,
+            (Some(ScatterKind::Diffuse), 1) => Pass::DiffuseDirect,
+            (Some(ScatterKind::Diffuse), _) => Pass::DiffuseIndirect,
+            (Some(ScatterKind::Specular), 1) => Pass::SpecularDirect,
+            (Some(ScatterKind::Specular), _) => Pass::SpecularIndirect,
+        }
+    }
+}
+
+/// The radiance gathered along a single camera path, split by light path expression and by the light
+/// group of the object that emitted it.
+#[derive(Debug, Clone)]
+pub struct PathSample {
+    pub color: Color,
+    pub passes: [Color; NUM_PASSES],
+    pub light_groups: Vec<Color>,
+}
+
+impl PathSample {
+    pub fn new(num_light_groups: usize) -> PathSample {
+        PathSample {
+            color: Color::black(),
+            passes: [Color::black(); NUM_PASSES],
+            light_groups: vec![Color::black(); num_light_groups],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.color = Color::black();
+        self.passes = [Color::black(); NUM_PASSES];
+        self.light_groups.iter_mut().for_each(|c| *c = Color::black());
+    }
+
+    pub fn add(&mut self, event: Option<ScatterKind>, bounce: u16, light_group: Option<usize>, c: Color) {
+        self.color += c;
+        self.passes[Pass::classify(event, bounce) as usize] += c;
+        if let Some(g) = light_group {
+            self.light_groups[g] += c;
+        }
+    }
+
+    pub fn scale(&mut self, f: f64) {
+        self.color = self.color * f;
+        self.passes.iter_mut().for_each(|c| *c = *c * f);
+        self.light_groups.iter_mut().for_each(|c| *c = *c * f);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_camera_vertex() {
+        assert_eq!(Pass::Emission, Pass::classify(None, 0));
+    }
+
+    #[test]
+    fn classify_direct_and_indirect() {
+        assert_eq!(Pass::DiffuseDirect, Pass::classify(Some(ScatterKind::Diffuse), 1));
+        assert_eq!(Pass::DiffuseIndirect, Pass::classify(Some(ScatterKind::Diffuse), 2));
+        assert_eq!(Pass::SpecularDirect, Pass::classify(Some(ScatterKind::Specular), 1));
+        assert_eq!(Pass::SpecularIndirect, Pass::classify(Some(ScatterKind::Specular), 5));
+        assert_eq!(Pass::Volume, Pass::classify(Some(ScatterKind::Volume), 1));
+        assert_eq!(Pass::Volume, Pass::classify(Some(ScatterKind::Volume), 3));
+        assert_eq!(Pass::Volume, Pass::classify(Some(ScatterKind::Subsurface), 2));
+    }
+
+    #[test]
+    fn passes_sum_to_beauty() {
+        let mut s = PathSample::new(0);
+        s.add(None, 0, None, Color::new(0.1, 0.2, 0.3));
+        s.add(Some(ScatterKind::Diffuse), 1, None, Color::new(0.4, 0.5, 0.6));
+        s.add(Some(ScatterKind::Diffuse), 2, None, Color::new(0.7, 0.8, 0.9));
+        let sum = s.passes.iter().fold(Color::black(), |acc, &c| acc + c);
+        assert_eq!(s.color, sum);
+        assert_eq!(Color::new(0.4, 0.5, 0.6), s.passes[Pass::DiffuseDirect as usize]);
+    }
+
+    #[test]
+    fn light_groups_only_receive_their_emission() {
+        let mut s = PathSample::new(2);
+        s.add(None, 0, None, Color::new(0.1, 0.1, 0.1));
+        s.add(Some(ScatterKind::Diffuse), 1, Some(1), Color::new(0.2, 0.3, 0.4));
+        assert_eq!(Color::black(), s.light_groups[0]);
+        assert_eq!(Color::new(0.2, 0.3, 0.4), s.light_groups[1]);
+
+        s.reset();
+        assert_eq!(Color::black(), s.color);
+        assert_eq!(Color::black(), s.light_groups[1]);
+    }
+}
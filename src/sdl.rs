@@ -5,40 +5,294 @@ use std::io::Read;
 use image;
 use wavefront_obj;
 
+use crate::camera::{
+    ApertureShape, Camera, Exposure, FisheyeMapping, Projection, Shutter, Stereo, StereoLayout, ThinLens,
+    look_at_transform,
+};
 use crate::color::Color;
-use crate::direction::Direction;
-use crate::materials::Material;
+use crate::direction::{Direction, Dot};
+use crate::lens::{self, LensElement, LensSystem};
+use crate::materials::{Material, Principled};
 use crate::matrix::Matrix44f;
+use crate::motion::Motion;
 use crate::object::Object;
 use crate::point::Point;
 use crate::sdl_grammar;
 use crate::shapes::{Composite, Mesh, MeshTriangle, Shape};
-use crate::system::{Camera, Options};
+use crate::system::{CropWindow, Options};
+use crate::texture::Texture;
 
 pub struct Scene {
     pub options: SceneOptions,
     pub camera: Camera,
     pub objects: Vec<Object>,
+    pub light_groups: Vec<String>,
 }
 
 pub struct SceneOptions {
     pub background_color: Color,
+    pub crop: Option<CropWindow>,
 }
 
 impl SceneOptions {
     pub fn default() -> SceneOptions {
         SceneOptions {
             background_color: Color::black(),
+            crop: None,
         }
     }
 }
 
+pub enum SceneOption {
+    Background(Color),
+    Crop(CropWindow),
+}
+
+pub fn new_scene_options(items: Vec<SceneOption>) -> SceneOptions {
+    let mut options = SceneOptions::default();
+    for item in items {
+        match item {
+            SceneOption::Background(c) => options.background_color = c,
+            SceneOption::Crop(w) => options.crop = Some(w),
+        }
+    }
+    options
+}
+
 pub fn parse(options: &Options, s: &str) -> Result<Scene, String> {
-    sdl_grammar::sdl_grammar::scene(&s, &options).map_err(|err| err.to_string())
+    sdl_grammar::sdl_grammar::scene(&s, options.frame as f64, &options).map_err(|err| err.to_string())
+}
+
+pub enum ProjectionKind {
+    Perspective,
+    Orthographic,
+    Equirectangular,
+    Cubemap,
+    Fisheye(FisheyeMapping),
+}
+
+pub enum CameraOption {
+    Origin(Point),
+    LookAt(Point),
+    Up(Direction),
+    Roll(f64),
+    Transform(Matrix44f),
+    Projection(ProjectionKind),
+    Fov(f64),
+    ViewWidth(f64),
+    Aperture(f64),
+    ApertureShape(ApertureShape),
+    FocusDistance(f64),
+    FocusPoint(Point),
+    Stereo(StereoLayout, f64, Option<f64>),
+    Shutter(f64, f64),
+    Iso(f64),
+    ShutterSpeed(f64),
+    FNumber(f64),
+    SensorWidth(f64),
+    Lens(Vec<LensElement>),
+}
+
+/// Width of a full frame 35mm sensor, in metres.
+const DEFAULT_SENSOR_WIDTH: f64 = 0.036;
+
+pub fn new_camera(options: &Options, items: Vec<CameraOption>) -> Result<Camera, &'static str> {
+    let mut origin = None;
+    let mut look_at = None;
+    let mut up = Direction::new(0.0, 1.0, 0.0);
+    let mut roll = 0.0;
+    let mut transform = None;
+    let mut projection = ProjectionKind::Perspective;
+    let mut fov = None;
+    let mut view_width = None;
+    let mut aperture: Option<f64> = None;
+    let mut aperture_shape = ApertureShape::Circle;
+    let mut focus_distance = None;
+    let mut focus_point = None;
+    let mut stereo = None;
+    let mut shutter = None;
+    let mut iso = None;
+    let mut shutter_speed = None;
+    let mut f_number = None;
+    let mut sensor_width = DEFAULT_SENSOR_WIDTH;
+    let mut lens = None;
+    for item in items {
+        match item {
+            CameraOption::Origin(p) => origin = Some(p),
+            CameraOption::LookAt(p) => look_at = Some(p),
+            CameraOption::Up(d) => up = d,
+            CameraOption::Roll(r) => roll = r,
+            CameraOption::Transform(m) => transform = Some(m),
+            CameraOption::Projection(p) => projection = p,
+            CameraOption::Fov(f) => fov = Some(f),
+            CameraOption::ViewWidth(w) => view_width = Some(w),
+            CameraOption::Aperture(a) => aperture = Some(a),
+            CameraOption::ApertureShape(s) => aperture_shape = s,
+            CameraOption::FocusDistance(d) => focus_distance = Some(d),
+            CameraOption::FocusPoint(p) => focus_point = Some(p),
+            CameraOption::Stereo(layout, interocular, convergence) => stereo = Some((layout, interocular, convergence)),
+            CameraOption::Shutter(open, close) => shutter = Some(Shutter { open, close }),
+            CameraOption::Iso(i) => iso = Some(i),
+            CameraOption::ShutterSpeed(s) => shutter_speed = Some(s),
+            CameraOption::FNumber(n) => f_number = Some(n),
+            CameraOption::SensorWidth(w) => sensor_width = w,
+            CameraOption::Lens(elements) => lens = Some(elements),
+        }
+    }
+    // with a transform, the camera starts out at the world origin looking down -z
+    let (origin, look_at) = match (origin, look_at, transform) {
+        (Some(o), Some(l), _) => (o, l),
+        (None, None, Some(_)) => (Point::zero(), Point::new(0.0, 0.0, -1.0)),
+        (None, _, _) => return Err("camera origin"),
+        (_, None, _) => return Err("camera look_at"),
+    };
+    if origin == look_at {
+        return Err("camera look_at different from origin");
+    }
+    if up.length() == 0.0 {
+        return Err("non-zero camera up");
+    }
+    let camera_to_world = Matrix44f::rotation_z(roll)
+        * look_at_transform(origin, look_at, up)
+        * transform.unwrap_or(Matrix44f::identity());
+
+    let projection = match projection {
+        ProjectionKind::Perspective => Projection::Perspective(fov.unwrap_or(60.0)),
+        ProjectionKind::Orthographic => Projection::Orthographic(view_width.ok_or("camera view_width")?),
+        ProjectionKind::Equirectangular => Projection::Equirectangular,
+        ProjectionKind::Cubemap => Projection::Cubemap,
+        ProjectionKind::Fisheye(mapping) => Projection::Fisheye(mapping, fov.unwrap_or(180.0)),
+    };
+    if aperture.is_some() && projection.is_panoramic() {
+        return Err("camera without aperture for a panoramic projection");
+    }
+    if lens.is_some() && (aperture.is_some() || stereo.is_some() || projection.is_panoramic()) {
+        return Err("camera lens without aperture, stereo or a panoramic projection");
+    }
+
+    let mut camera = Camera::new(options.width as f64, options.height as f64, projection, camera_to_world);
+
+    // physical exposure settings, with the remaining ones taken from a typical camera
+    if iso.is_some() || shutter_speed.is_some() || f_number.is_some() {
+        let default = Exposure::default();
+        let exposure = Exposure {
+            iso: iso.unwrap_or(default.iso),
+            shutter_speed: shutter_speed.unwrap_or(default.shutter_speed),
+            f_number: f_number.unwrap_or(default.f_number),
+        };
+        camera.exposure = exposure.multiplier();
+        // the f-number sizes the lens unless the aperture is given directly
+        if let (Projection::Perspective(fov), Some(_), None, None) = (projection, f_number, aperture, &lens) {
+            let aspect_ratio = options.width as f64 / options.height as f64;
+            let focal_length = sensor_width * 0.5 / ((fov * 0.5).to_radians().tan() * aspect_ratio);
+            aperture = Some(exposure.aperture(focal_length));
+        }
+    }
+    if aperture.is_some() || lens.is_some() {
+        let focus_distance = match (focus_distance, focus_point) {
+            (Some(d), _) => d,
+            (None, Some(p)) => camera.view_direction().dot(p - camera.origin()),
+            (None, None) => (look_at - origin).length(),
+        };
+        if focus_distance <= 0.0 {
+            return Err("focus in front of the camera");
+        }
+        if let Some(aperture) = aperture {
+            camera.lens = Some(ThinLens::new(aperture, focus_distance, aperture_shape));
+        }
+        if let Some(elements) = lens {
+            let mut lens_system = LensSystem::new(elements, sensor_width, Some(focus_distance))
+                .ok_or("camera lens that can focus at the focus distance")?;
+            if let Some(f_number) = f_number {
+                lens_system = lens_system
+                    .with_f_number(f_number)
+                    .ok_or("camera f_number that the lens can stop down to")?;
+            }
+            camera.lens_system = Some(lens_system);
+        }
+    }
+    if let Some((layout, interocular, convergence)) = stereo {
+        if let Projection::Cubemap | Projection::Fisheye(..) = projection {
+            return Err("camera stereo with a perspective, orthographic or equirectangular projection");
+        }
+        camera.stereo = Some(Stereo {
+            layout,
+            interocular,
+            convergence: convergence.unwrap_or((look_at - origin).length()),
+        });
+    }
+    camera.shutter = shutter;
+    Ok(camera)
+}
+
+pub enum PrincipledOption {
+    BaseColor(Texture),
+    Metallic(Texture),
+    Roughness(Texture),
+    Specular(Texture),
+    SpecularTint(Texture),
+    Sheen(Texture),
+    SheenTint(Texture),
+    Clearcoat(Texture),
+    ClearcoatRoughness(Texture),
+    Transmission(Texture),
+    Ior(Texture),
 }
 
-pub fn new_object(name: Option<String>, shape: Box<dyn Shape>, material: Box<dyn Material>) -> Object {
-    Object::new(&name.unwrap_or(String::from("object")), shape, material)
+pub fn new_principled(items: Vec<PrincipledOption>) -> Principled {
+    let mut principled = Principled::new(Texture::Solid(Color::new(0.8, 0.8, 0.8)));
+    for item in items {
+        match item {
+            PrincipledOption::BaseColor(t) => principled.base_color = t,
+            PrincipledOption::Metallic(t) => principled.metallic = t,
+            PrincipledOption::Roughness(t) => principled.roughness = t,
+            PrincipledOption::Specular(t) => principled.specular = t,
+            PrincipledOption::SpecularTint(t) => principled.specular_tint = t,
+            PrincipledOption::Sheen(t) => principled.sheen = t,
+            PrincipledOption::SheenTint(t) => principled.sheen_tint = t,
+            PrincipledOption::Clearcoat(t) => principled.clearcoat = t,
+            PrincipledOption::ClearcoatRoughness(t) => principled.clearcoat_roughness = t,
+            PrincipledOption::Transmission(t) => principled.transmission = t,
+            PrincipledOption::Ior(t) => principled.ior = t,
+        }
+    }
+    principled
+}
+
+pub fn new_scene(options: Option<SceneOptions>, camera: Camera, mut objects: Vec<Object>) -> Scene {
+    let mut light_groups: Vec<String> = Vec::new();
+    for object in objects.iter_mut() {
+        if let Some(group) = &object.light_group {
+            let index = match light_groups.iter().position(|g| g == group) {
+                Some(index) => index,
+                None => {
+                    light_groups.push(group.clone());
+                    light_groups.len() - 1
+                }
+            };
+            object.light_group_index = Some(index);
+        }
+    }
+
+    Scene {
+        options: options.unwrap_or(SceneOptions::default()),
+        camera,
+        objects,
+        light_groups,
+    }
+}
+
+pub fn new_object(
+    name: Option<String>,
+    shape: Box<dyn Shape>,
+    material: Box<dyn Material>,
+    light_group: Option<String>,
+    motion: Option<Vec<(f64, Matrix44f)>>,
+) -> Object {
+    let mut object = Object::new(&name.unwrap_or(String::from("object")), shape, material);
+    object.light_group = light_group;
+    object.motion = motion.map(Motion::new);
+    object
 }
 
 pub fn transform_shape(mut shape: Box<dyn Shape>, transform: Option<Matrix44f>) -> Box<dyn Shape> {
@@ -62,6 +316,15 @@ pub fn load_mesh_file(path: &str) -> Box<dyn Shape> {
     convert_objs(&obj_set)
 }
 
+pub fn load_lens_file(path: &str, scale: f64) -> Vec<LensElement> {
+    let mut lens_file = File::open(path).expect("could not open lens file");
+    let mut lens_file_contents = String::new();
+    lens_file
+        .read_to_string(&mut lens_file_contents)
+        .expect("could not read lens file");
+    lens::parse_prescription(&lens_file_contents, scale).expect("could not parse lens file")
+}
+
 fn convert_objs(objs: &wavefront_obj::obj::ObjSet) -> Box<dyn Shape> {
     let shapes: Vec<Mesh> = objs
         .objects
@@ -94,3 +357,94 @@ fn convert_objs(objs: &wavefront_obj::obj::ObjSet) -> Box<dyn Shape> {
 pub fn combine_transforms(transforms: Vec<Matrix44f>) -> Matrix44f {
     transforms.iter().fold(Matrix44f::identity(), |acc, &m| acc * m)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_utils::*;
+
+    /// The shape of a full frame sensor.
+    fn options() -> Options {
+        Options {
+            width: 360,
+            height: 240,
+            ..test_options()
+        }
+    }
+
+    fn camera(settings: &str) -> Result<Scene, String> {
+        let scene = format!(
+            "camera {{ {} }}
+            object {{ sphere {{ origin <0, 0, -10> radius 1 }} material {{ lambertian texture {{ solid color white }} }} }}",
+            settings
+        );
+        parse(&options(), &scene)
+    }
+
+    fn lens_camera(settings: &str) -> Result<Scene, String> {
+        camera(&format!(
+            "origin <0, 0, 0> look_at <0, 0, -10> lens \"scenes/lenses/dgauss.50mm.lens\" {}",
+            settings
+        ))
+    }
+
+    #[test]
+    pub fn lens_file_sees_through_full_frame_sensor() {
+        let scene = lens_camera("").unwrap();
+        // a 50mm lens on a 36mm wide sensor sees about 20 degrees either side of the view direction
+        let r = (0..100).find_map(|_| scene.camera.pixel_ray(359.5, 120.0)).unwrap();
+        let angle = r.direction.x.atan2(-r.direction.z).to_degrees();
+        assert!((angle - 19.8).abs() < 2.0, "edge of the image at {} degrees", angle);
+    }
+
+    #[test]
+    pub fn lens_f_number() {
+        assert!(lens_camera("f_number 8").is_ok());
+        assert!(lens_camera("f_number 1").is_err());
+        assert!(lens_camera("aperture 0.1").is_err());
+    }
+
+    #[test]
+    pub fn roll_turns_camera_about_view_direction() {
+        let scene = camera("origin <0, 0, 0> look_at <0, 0, -1> roll 90").unwrap();
+        assert_approx_eq!(scene.camera.view_direction(), Direction::new(0.0, 0.0, -1.0));
+        // the top of the image turns towards -x, anticlockwise as the camera sees it
+        let top = scene.camera.pixel_ray(180.0, 0.0).unwrap().direction;
+        assert!(top.x < -0.1);
+        assert_approx_eq!(top.y, 0.0);
+    }
+
+    #[test]
+    pub fn transform_places_camera() {
+        let scene = camera("transform { rotate_y 90 translate <1, 2, 3> }").unwrap();
+        assert_approx_eq!(scene.camera.origin(), Point::new(1.0, 2.0, 3.0));
+        assert_approx_eq!(scene.camera.view_direction(), Direction::new(-1.0, 0.0, 0.0));
+        let top = scene.camera.pixel_ray(180.0, 0.0).unwrap().direction;
+        assert!(top.y > 0.1);
+    }
+
+    #[test]
+    pub fn light_groups_resolved_on_objects() {
+        let light = |group: &str| {
+            format!(
+                "object {{ sphere {{ origin <0, 0, -5> radius 1 }} material {{ diffuse_light intensity 1 texture {{ solid color rgb <1, 1, 1> }} }} {} }}",
+                group
+            )
+        };
+        let scene = parse(
+            &options(),
+            &format!(
+                "camera {{ origin <0, 0, 0> look_at <0, 0, -1> fov 40 }} {} {} {} {}",
+                light("light_group \"key\""),
+                light(""),
+                light("light_group \"fill\""),
+                light("light_group \"key\""),
+            ),
+        )
+        .unwrap();
+        assert_eq!(vec!["key", "fill"], scene.light_groups);
+        let indices: Vec<Option<usize>> = scene.objects.iter().map(|o| o.light_group_index).collect();
+        assert_eq!(vec![Some(0), None, Some(1), Some(0)], indices);
+    }
+}
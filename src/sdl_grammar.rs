@@ -1,53 +1,188 @@
 use std::str::FromStr;
 
+use crate::animation;
+use crate::animation::Interpolation;
+use crate::camera::{ApertureMask, ApertureShape, Camera, FisheyeMapping, StereoLayout};
 use crate::color::Color;
 use crate::direction::Direction;
+use crate::lens::LensElement;
 use crate::materials::*;
 use crate::matrix::Matrix44f;
 use crate::object::Object;
 use crate::point::Point;
 use crate::sdl;
-use crate::sdl::{Scene, SceneOptions};
+use crate::sdl::{CameraOption, PrincipledOption, ProjectionKind, Scene, SceneOption, SceneOptions};
 use crate::shapes::*;
-use crate::system::{Camera, Options};
+use crate::system::{CropWindow, Options};
 use crate::texture::{Pattern, Texture};
 
 peg::parser! {
 
-    pub grammar sdl_grammar() for str {
+    pub grammar sdl_grammar(frame: f64) for str {
 
         pub rule scene(render_options: &Options) -> Scene
             = options:options()? _ camera:camera(render_options) _ objects:one_or_more(<object()>) {
-                Scene {
-                options: options.unwrap_or(SceneOptions::default()),
-                camera,
-                objects,
-                }
+                sdl::new_scene(options, camera, objects)
             }
 
         rule options() -> SceneOptions
-            = "options" _ "{" _ bg:bg() _ "}" {
-                SceneOptions {
-                background_color: bg,
-                }
+            = "options" _ "{" _ items:zero_or_more(<scene_option()>) _ "}" {
+                sdl::new_scene_options(items)
             }
 
+        rule scene_option() -> SceneOption
+            = c:bg() { SceneOption::Background(c) }
+            / w:crop() { SceneOption::Crop(w) }
+
         rule bg() -> Color = "background" _ color:color() { color }
 
+        rule crop() -> CropWindow
+            = "crop" _ x0:uint() _ y0:uint() _ x1:uint() _ y1:uint() {?
+                if x0 < x1 && y0 < y1 {
+                    Ok(CropWindow::new(x0, y0, x1, y1))
+                } else {
+                    Err("crop window with x0 < x1 and y0 < y1")
+                }
+            }
+
         pub rule camera(render_options: &Options) -> Camera
-            = "camera" _ "{" _ o:origin() _ p:camera_lookat() _ fov:fov()? _ "}" {
-                Camera::new(render_options.width as f64, render_options.height as f64, fov.unwrap_or(60.0), o, p)
+            = "camera" _ "{" _ items:zero_or_more(<camera_option()>) _ "}" {?
+                sdl::new_camera(render_options, items)
+            }
+
+        rule camera_option() -> CameraOption
+            = p:origin() { CameraOption::Origin(p) }
+            / p:camera_lookat() { CameraOption::LookAt(p) }
+            / d:camera_up() { CameraOption::Up(d) }
+            / r:camera_roll() { CameraOption::Roll(r) }
+            / m:transforms() { CameraOption::Transform(m) }
+            / p:projection() { CameraOption::Projection(p) }
+            / f:fov() { CameraOption::Fov(f) }
+            / w:view_width() { CameraOption::ViewWidth(w) }
+            / a:aperture() { CameraOption::Aperture(a) }
+            / s:aperture_shape() { CameraOption::ApertureShape(s) }
+            / d:focus_distance() { CameraOption::FocusDistance(d) }
+            / p:focus_point() { CameraOption::FocusPoint(p) }
+            / s:stereo() { s }
+            / s:shutter_speed() { CameraOption::ShutterSpeed(s) }
+            / s:shutter() { s }
+            / i:iso() { CameraOption::Iso(i) }
+            / n:f_number() { CameraOption::FNumber(n) }
+            / w:sensor_width() { CameraOption::SensorWidth(w) }
+            / l:lens() { CameraOption::Lens(l) }
+
+        rule camera_lookat() -> Point = "look_at" _ p:animated_point() { p }
+
+        rule camera_up() -> Direction = "up" _ d:animated_direction() { d }
+
+        rule camera_roll() -> f64 = "roll" _ r:animated_float() { r }
+
+        rule projection() -> ProjectionKind
+            = "projection" _ p:(
+                "perspective" { ProjectionKind::Perspective }
+                / "orthographic" { ProjectionKind::Orthographic }
+                / "equirectangular" { ProjectionKind::Equirectangular }
+                / "cubemap" { ProjectionKind::Cubemap }
+                / "fisheye" _ m:fisheye_mapping() { ProjectionKind::Fisheye(m) }
+            ) { p }
+
+        rule fisheye_mapping() -> FisheyeMapping
+            = "equidistant" { FisheyeMapping::Equidistant }
+            / "equisolid" { FisheyeMapping::Equisolid }
+
+        rule fov() -> f64 = "fov" _ f:animated_float() { f }
+
+        rule view_width() -> f64
+            = "view_width" _ w:float() {?
+                if w > 0.0 { Ok(w) } else { Err("positive view_width") }
+            }
+
+        rule iso() -> f64 = "iso" _ i:positive_float() { i }
+
+        // seconds, optionally written as a fraction like 1/125
+        rule shutter_speed() -> f64
+            = "shutter_speed" _ n:positive_float() d:(_ "/" _ d:positive_float() { d })? {
+                n / d.unwrap_or(1.0)
+            }
+
+        rule f_number() -> f64 = "f_number" _ n:positive_float() { n }
+
+        rule sensor_width() -> f64 = "sensor_width" _ w:positive_float() { w }
+
+        rule positive_float() -> f64
+            = f:animated_float() {?
+                if f > 0.0 { Ok(f) } else { Err("positive number") }
             }
 
-        rule camera_lookat() -> Point = "look_at" _ p:point() { p }
+        rule shutter() -> CameraOption
+            = "shutter" _ open:float() _ close:float() {?
+                if open <= close { Ok(CameraOption::Shutter(open, close)) } else { Err("shutter open before close") }
+            }
+
+        rule stereo() -> CameraOption
+            = "stereo" _ layout:stereo_layout() _ "interocular" _ i:float() c:(_ "convergence" _ c:float() { c })? {?
+                if c.is_some_and(|c| c <= 0.0) {
+                    Err("positive stereo convergence")
+                } else {
+                    Ok(CameraOption::Stereo(layout, i, c))
+                }
+            }
+
+        rule stereo_layout() -> StereoLayout
+            = "side_by_side" { StereoLayout::SideBySide }
+            / "over_under" { StereoLayout::OverUnder }
+
+        rule aperture() -> f64 = "aperture" _ a:animated_float() { a }
+
+        rule aperture_shape() -> ApertureShape
+            = "aperture_shape" _ s:(aperture_polygon() / aperture_image()) { s }
+
+        rule aperture_polygon() -> ApertureShape
+            = "polygon" _ blades:uint() _ r:aperture_rotation()? {?
+                if blades >= 3 {
+                    Ok(ApertureShape::Polygon(blades, r.unwrap_or(0.0)))
+                } else {
+                    Err("at least 3 aperture blades")
+                }
+            }
+
+        rule aperture_rotation() -> f64 = "rotation" _ r:float() { r }
 
-        rule fov() -> f64 = "fov" _ f:float() { f }
+        rule aperture_image() -> ApertureShape
+            = "image" _ p:path() {?
+                ApertureMask::new(&sdl::load_image(&p))
+                    .map(ApertureShape::Image)
+                    .ok_or("aperture mask that lets light through the circle inside it")
+            }
+
+        // a lens prescription file, with its lengths multiplied by the scale to get scene units. Lens
+        // files are in millimetres, which the default scale turns into metres like the sensor width.
+        rule lens() -> Vec<LensElement>
+            = "lens" _ p:path() s:(_ "scale" _ s:positive_float() { s })? {
+                sdl::load_lens_file(&p, s.unwrap_or(0.001))
+            }
+
+        rule focus_distance() -> f64 = "focus_distance" _ d:animated_float() { d }
+
+        rule focus_point() -> Point = "focus_point" _ p:animated_point() { p }
 
         pub rule object() -> Object
-            = "object" _ name:string()? _ "{" _ shape:object_shape() _ material:object_material() _ "}" {
-                sdl::new_object(name, shape, material)
+            = "object" _ name:string()? _ "{" _ shape:object_shape() _ material:object_material() _ group:light_group()? _ motion:motion()? _ "}" {
+                sdl::new_object(name, shape, material, group, motion)
             }
 
+        rule motion() -> Vec<(f64, Matrix44f)>
+            = "motion" _ "{" _ keyframes:one_or_more(<motion_keyframe()>) _ "}" {?
+                if keyframes.len() >= 2 { Ok(keyframes) } else { Err("at least 2 motion keyframes") }
+            }
+
+        rule motion_keyframe() -> (f64, Matrix44f)
+            = "start" _ m:transforms() { (0.0, m) }
+            / "end" _ m:transforms() { (1.0, m) }
+            / "time" _ t:float() _ m:transforms() { (t, m) }
+
+        rule light_group() -> String = "light_group" _ g:string() { g }
+
         rule object_shape() -> Box<dyn Shape>
             = planar_shape()
             / solid_shape()
@@ -147,7 +282,7 @@ peg::parser! {
 
         rule reverse() -> () = "reverse"
 
-        rule origin() -> Point = "origin" _ p:point() { p }
+        rule origin() -> Point = "origin" _ p:animated_point() { p }
 
         rule width() -> f64 = "width" _ w:float() { w }
 
@@ -157,7 +292,13 @@ peg::parser! {
 
         rule material() -> Box<dyn Material>
             = lambertian()
+            / oren_nayar()
             / metal()
+            / conductor()
+            / principled()
+            / coated()
+            / mix()
+            / subsurface()
             / dielectric()
             / diffuse_light()
             / isotropic()
@@ -167,26 +308,145 @@ peg::parser! {
                 Box::new(Lambertian::new(texture))
             }
 
+        rule oren_nayar() -> Box<dyn Material>
+            = "oren_nayar" _ sigma:sigma() _ texture:texture() {
+                Box::new(OrenNayar::new(sigma, texture))
+            }
+
+        // standard deviation of the surface facet angles, in degrees
+        rule sigma() -> f64
+            = "sigma" _ s:animated_float() {?
+                if (0.0..=90.0).contains(&s) { Ok(s) } else { Err("sigma between 0 and 90 degrees") }
+            }
+
         rule metal() -> Box<dyn Material>
             = "metal" _ fuzz:fuzz() _ texture:texture() {
                 Box::new(Metal::new(fuzz, texture))
             }
 
+        rule coated() -> Box<dyn Material>
+            = "coated" _ "ior" _ ior:animated_float() absorption:(_ a:coating_absorption() { a })? _ "base" _ "{" _ base:material() _ "}" {
+                Box::new(Coated::new(ior, absorption, base))
+            }
+
+        rule coating_absorption() -> (f64, Absorption)
+            = "thickness" _ t:positive_float() _ a:absorption() { (t, a) }
+
+        // the weight is how much of the second material there is
+        rule mix() -> Box<dyn Material>
+            = "mix" _ "weight" _ weight:parameter_texture() _ "{" _ first:material() _ "}" _ "{" _ second:material() _ "}" {
+                Box::new(Mix::new(weight, first, second))
+            }
+
+        rule subsurface() -> Box<dyn Material>
+            = "subsurface" _ "ior" _ ior:animated_float() _ "mean_free_path" _ mfp:mean_free_path() _ texture:texture() {
+                Box::new(Subsurface::new(ior, texture, mfp))
+            }
+
+        // distance between scattering events, for each colour or for all of them
+        rule mean_free_path() -> Color
+            = c:animated_color() {?
+                if c.r > 0.0 && c.g > 0.0 && c.b > 0.0 { Ok(c) } else { Err("positive mean free path") }
+            }
+            / d:positive_float() { Color::new(d, d, d) }
+
+        rule principled() -> Box<dyn Material>
+            = "principled" _ "{" _ items:zero_or_more(<principled_option()>) _ "}" {
+                Box::new(sdl::new_principled(items))
+            }
+
+        rule principled_option() -> PrincipledOption
+            = "base_color" _ t:parameter_texture() { PrincipledOption::BaseColor(t) }
+            / "metallic" _ t:parameter_texture() { PrincipledOption::Metallic(t) }
+            / "roughness" _ t:parameter_texture() { PrincipledOption::Roughness(t) }
+            / "specular_tint" _ t:parameter_texture() { PrincipledOption::SpecularTint(t) }
+            / "specular" _ t:parameter_texture() { PrincipledOption::Specular(t) }
+            / "sheen_tint" _ t:parameter_texture() { PrincipledOption::SheenTint(t) }
+            / "sheen" _ t:parameter_texture() { PrincipledOption::Sheen(t) }
+            / "clearcoat_roughness" _ t:parameter_texture() { PrincipledOption::ClearcoatRoughness(t) }
+            / "clearcoat" _ t:parameter_texture() { PrincipledOption::Clearcoat(t) }
+            / "transmission" _ t:parameter_texture() { PrincipledOption::Transmission(t) }
+            / "ior" _ t:parameter_texture() { PrincipledOption::Ior(t) }
+
+        // a material parameter given as a texture, a colour or a single number
+        rule parameter_texture() -> Texture
+            = texture()
+            / c:animated_color() { Texture::Solid(c) }
+            / v:animated_float() { Texture::Solid(Color::new(v, v, v)) }
+
+        rule conductor() -> Box<dyn Material>
+            = "conductor" _ ior:conductor_ior() _ microfacet:microfacet() {
+                let (eta, k) = ior;
+                Box::new(Conductor::new(eta, k, microfacet))
+            }
+
+        rule conductor_ior() -> (Color, Color)
+            = p:conductor_preset() { p.ior() }
+            / "eta" _ eta:animated_color() _ "k" _ k:animated_color() { (eta, k) }
+
+        rule conductor_preset() -> ConductorPreset
+            = "gold" { ConductorPreset::Gold }
+            / "copper" { ConductorPreset::Copper }
+            / ("aluminium" / "aluminum") { ConductorPreset::Aluminium }
+            / "silver" { ConductorPreset::Silver }
+
+        rule microfacet() -> Microfacet
+            = r:roughness() a:(_ a:anisotropy() { a })? d:(_ d:microfacet_distribution() { d })? {
+                Microfacet::new(d.unwrap_or(Distribution::Ggx), r, a.unwrap_or(0.0))
+            }
+
+        rule roughness() -> f64
+            = "roughness" _ r:animated_float() {?
+                if (0.0..=1.0).contains(&r) { Ok(r) } else { Err("roughness between 0 and 1") }
+            }
+
+        rule anisotropy() -> f64
+            = "anisotropy" _ a:animated_float() {?
+                if (-1.0..=1.0).contains(&a) { Ok(a) } else { Err("anisotropy between -1 and 1") }
+            }
+
+        rule microfacet_distribution() -> Distribution
+            = "distribution" _ d:("ggx" { Distribution::Ggx } / "beckmann" { Distribution::Beckmann }) { d }
+
         rule dielectric() -> Box<dyn Material>
-            = "dielectric" _ ior:ior() _ fuzz:fuzz()? {
-                Box::new(Dielectric::new(ior, fuzz.unwrap_or(0.0)))
+            = "dielectric" _ ior:ior() microfacet:(_ m:microfacet() { m })? absorption:(_ a:absorption() { a })? {
+                Box::new(Dielectric::new(ior, microfacet, absorption))
             }
 
-        rule fuzz() -> f64 = "fuzz" _ n:float() { n }
+        // the color white light fades to after travelling the distance through the medium
+        rule absorption() -> Absorption
+            = "transmittance" _ c:animated_color() _ "distance" _ d:positive_float() {?
+                if [c.r, c.g, c.b].iter().all(|v| (0.0..=1.0).contains(v)) {
+                    Ok(Absorption { transmittance: c, distance: d })
+                } else {
+                    Err("transmittance color components between 0 and 1")
+                }
+            }
 
-        rule ior() -> f64 = "ior" _ n:float() { n }
+        rule fuzz() -> f64 = "fuzz" _ n:animated_float() { n }
+
+        rule ior() -> Ior
+            = "ior" _ i:(
+                "cauchy" _ a:float() _ b:float() { Ior::Cauchy(a, b) }
+                / "sellmeier" _ b1:float() _ b2:float() _ b3:float() _ c1:float() _ c2:float() _ c3:float() {
+                    Ior::Sellmeier([b1, b2, b3], [c1, c2, c3])
+                }
+                / "abbe" _ nd:float() _ vd:positive_float() { Ior::from_abbe(nd, vd) }
+                / n:animated_float() { Ior::Constant(n) }
+            ) { i }
 
         rule diffuse_light() -> Box<dyn Material>
             = "diffuse_light" _ i:intensity() _ texture:texture() {
                 Box::new(DiffuseLight::new(i, texture))
             }
+            / "diffuse_light" _ i:intensity() _ t:blackbody() {
+                Box::new(DiffuseLight::blackbody(i, t))
+            }
 
-        rule intensity() -> f64 = "intensity" _ n:float() { n }
+        // temperature in kelvin
+        rule blackbody() -> f64 = "blackbody" _ t:positive_float() { t }
+
+        rule intensity() -> f64 = "intensity" _ n:animated_float() { n }
 
         rule isotropic() -> Box<dyn Material>
             = "isotropic" _ texture:texture() {
@@ -204,7 +464,7 @@ peg::parser! {
             / scale()
 
         rule translate() -> Matrix44f
-            = "translate" _ d:direction() {
+            = "translate" _ d:animated_direction() {
                 Matrix44f::translation(d)
             }
 
@@ -214,22 +474,22 @@ peg::parser! {
             / rotate_z()
 
         rule rotate_x() -> Matrix44f
-            = "rotate_x" _ n:float() {
+            = "rotate_x" _ n:animated_float() {
                 Matrix44f::rotation_x(n)
             }
 
         rule rotate_y() -> Matrix44f
-            = "rotate_y" _ n:float() {
+            = "rotate_y" _ n:animated_float() {
                 Matrix44f::rotation_y(n)
             }
 
         rule rotate_z() -> Matrix44f
-            = "rotate_z" _ n:float() {
+            = "rotate_z" _ n:animated_float() {
                 Matrix44f::rotation_z(n)
             }
 
         rule scale() -> Matrix44f
-            = "scale" _ d:direction() {
+            = "scale" _ d:animated_direction() {
                 Matrix44f::scaling(d)
             }
 
@@ -237,7 +497,7 @@ peg::parser! {
             = "texture" _ "{" _ t:(texture_solid() / texture_pattern() / texture_image()) _ "}" { t }
 
         rule texture_solid() -> Texture
-            = "solid" _ c:color() {
+            = "solid" _ c:animated_color() {
                 Texture::Solid(c)
             }
 
@@ -263,6 +523,33 @@ peg::parser! {
                 String::from_str(s).unwrap()
             }
 
+        rule animated_float() -> f64
+            = float()
+            / a:animation(<float()>) { animation::evaluate(&a.1, a.0, frame) }
+
+        rule animated_point() -> Point
+            = point()
+            / a:animation(<point()>) { animation::evaluate(&a.1, a.0, frame) }
+
+        rule animated_direction() -> Direction
+            = direction()
+            / a:animation(<direction()>) { animation::evaluate(&a.1, a.0, frame) }
+
+        rule animated_color() -> Color
+            = color()
+            / a:animation(<color()>) { animation::evaluate(&a.1, a.0, frame) }
+
+        rule animation<T>(value: rule<T>) -> (Interpolation, Vec<(f64, T)>)
+            = "animate" _ i:interpolation()? _ "{" _ keyframes:("frame" _ f:float() _ v:value() _ { (f, v) })+ _ "}" {
+                (i.unwrap_or(Interpolation::Linear), keyframes)
+            }
+
+        rule interpolation() -> Interpolation
+            = "step" { Interpolation::Step }
+            / "linear" { Interpolation::Linear }
+            / "smooth" { Interpolation::Smooth }
+            / "spline" { Interpolation::Spline }
+
         pub rule point() -> Point
             = v:vec3() {
                 Point::from_tuple(v)
@@ -312,6 +599,14 @@ peg::parser! {
             }
             / expected!("float literal")
 
+        pub rule uint() -> u32
+            = quiet!{
+                s:$(digit()+) {?
+                    u32::from_str(s).or(Err("unsigned integer"))
+                }
+            }
+            / expected!("unsigned integer")
+
         rule digit() = ['0'..='9']
 
         rule zero_or_more<E>(elem: rule<E>) -> Vec<E> = v:(e:elem() _ { e })* { v }
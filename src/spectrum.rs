@@ -0,0 +1,314 @@
+use std::sync::LazyLock;
+
+use rand::Rng;
+
+use crate::color::Color;
+use crate::matrix::Matrix44f;
+use crate::system::Ray;
+
+/// Range of visible wavelengths, in nanometres.
+pub const WAVELENGTH_MIN: f64 = 380.0;
+pub const WAVELENGTH_MAX: f64 = 780.0;
+
+/// Picks a visible wavelength uniformly.
+pub fn sample_wavelength() -> f64 {
+    WAVELENGTH_MIN + rand::rng().random::<f64>() * (WAVELENGTH_MAX - WAVELENGTH_MIN)
+}
+
+/// CIE 1931 colour matching functions, using the multi-lobe fit from Wyman et al., "Simple Analytic
+/// Approximations to the CIE XYZ Color Matching Functions".
+pub fn cie_xyz(wavelength: f64) -> (f64, f64, f64) {
+    let g = |mu: f64, s1: f64, s2: f64| {
+        let t = (wavelength - mu) / if wavelength < mu { s1 } else { s2 };
+        (-0.5 * t * t).exp()
+    };
+    (
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+/// Converts CIE XYZ to linear sRGB.
+pub fn xyz_to_rgb((x, y, z): (f64, f64, f64)) -> Color {
+    Color::new(
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z,
+    )
+}
+
+/// Average colour of the clamped wavelength colours over the visible range, to keep white light white.
+static WAVELENGTH_COLOR_MEAN: LazyLock<Color> = LazyLock::new(|| {
+    let steps = (WAVELENGTH_MAX - WAVELENGTH_MIN) as usize;
+    let sum = (0..steps)
+        .map(|i| clamped_rgb(WAVELENGTH_MIN + i as f64 + 0.5))
+        .fold(Color::black(), |acc, c| acc + c);
+    sum / steps as f64
+});
+
+fn clamped_rgb(wavelength: f64) -> Color {
+    let c = xyz_to_rgb(cie_xyz(wavelength));
+    Color::new(c.r.max(0.0), c.g.max(0.0), c.b.max(0.0))
+}
+
+/// Colour carried by light of a single wavelength picked with `sample_wavelength`, scaled so that
+/// the colours of all the wavelengths average to white.
+pub fn wavelength_color(wavelength: f64) -> Color {
+    let c = clamped_rgb(wavelength);
+    let mean = *WAVELENGTH_COLOR_MEAN;
+    Color::new(c.r / mean.r, c.g / mean.g, c.b / mean.b)
+}
+
+/// Boundaries between the blue, green and red bands of the spectra that RGB colours are upsampled to.
+const BLUE_GREEN: f64 = 488.0;
+const GREEN_RED: f64 = 576.0;
+
+/// Index of the red (0), green (1) or blue (2) band that the wavelength falls in.
+fn band(wavelength: f64) -> usize {
+    if wavelength < BLUE_GREEN {
+        2
+    } else if wavelength < GREEN_RED {
+        1
+    } else {
+        0
+    }
+}
+
+/// Takes an RGB colour to the heights of the red, green and blue bands of its spectrum. The colour
+/// of each band seen through `wavelength_color` spills into the other channels, so this is the
+/// inverse of the matrix of those colours, which makes the spectrum give back the colour it came from.
+static BAND_HEIGHTS: LazyLock<Matrix44f> = LazyLock::new(|| {
+    // over the same steps as `WAVELENGTH_COLOR_MEAN`, so that the colours of the bands add up to white
+    let steps = (WAVELENGTH_MAX - WAVELENGTH_MIN) as usize;
+    let mut band_colors = Matrix44f::identity();
+    band_colors[0][0] = 0.0;
+    band_colors[1][1] = 0.0;
+    band_colors[2][2] = 0.0;
+    for i in 0..steps {
+        let wavelength = WAVELENGTH_MIN + i as f64 + 0.5;
+        let c = wavelength_color(wavelength) / steps as f64;
+        let b = band(wavelength);
+        band_colors[0][b] += c.r;
+        band_colors[1][b] += c.g;
+        band_colors[2][b] += c.b;
+    }
+    band_colors.inverse()
+});
+
+/// Value at the given wavelength of a spectrum with the RGB colour, made of red, green and blue bands
+/// calibrated against `wavelength_color`, so that the colour comes back when the spectrum is seen
+/// through it. Grey stays flat, but saturated colours dip a little below zero in the other bands.
+pub fn rgb_to_spectrum(c: Color, wavelength: f64) -> f64 {
+    let m = *BAND_HEIGHTS;
+    let b = band(wavelength);
+    m[b][0] * c.r + m[b][1] * c.g + m[b][2] * c.b
+}
+
+/// The component of the RGB value for the band that the wavelength falls in, for quantities other
+/// than colours, such as distances.
+pub fn rgb_band_value(c: Color, wavelength: f64) -> f64 {
+    [c.r, c.g, c.b][band(wavelength)]
+}
+
+/// Wavelength that a path carries on with after a material whose effect depends on the wavelength, if
+/// it is `chromatic`, and the colour of that wavelength. An RGB path picks a wavelength and takes on
+/// its colour, a spectral path keeps its hero wavelength, and a path already limited to a single
+/// wavelength keeps that one.
+pub fn path_wavelength(chromatic: bool, incident: &Ray) -> (Option<f64>, Color) {
+    match (chromatic, incident.wavelength, incident.wavelengths) {
+        (true, None, Some(wavelengths)) => (Some(wavelengths.hero()), Color::white()),
+        (true, None, None) => {
+            let wavelength = sample_wavelength();
+            (Some(wavelength), wavelength_color(wavelength))
+        }
+        (_, wavelength, _) => (wavelength, Color::white()),
+    }
+}
+
+/// The wavelengths in nanometres carried by a path in spectral rendering: a hero wavelength picked
+/// uniformly, with the others spread evenly through the visible range after it. Values at each
+/// wavelength are packed into the red, green and blue channels of a `Color`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Wavelengths(pub [f64; 3]);
+
+impl Wavelengths {
+    pub fn sample() -> Wavelengths {
+        Wavelengths::from_hero(sample_wavelength())
+    }
+
+    pub fn from_hero(hero: f64) -> Wavelengths {
+        let range = WAVELENGTH_MAX - WAVELENGTH_MIN;
+        let rotate = |i: f64| WAVELENGTH_MIN + (hero - WAVELENGTH_MIN + i * range / 3.0) % range;
+        Wavelengths([hero, rotate(1.0), rotate(2.0)])
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.0[0]
+    }
+
+    /// Values of a spectrum at each of the wavelengths.
+    pub fn sample_spectrum<F: Fn(f64) -> f64>(&self, spectrum: F) -> Color {
+        Color::new(spectrum(self.0[0]), spectrum(self.0[1]), spectrum(self.0[2]))
+    }
+
+    /// Values of the spectrum upsampled from an RGB colour at each of the wavelengths.
+    pub fn upsample(&self, c: Color) -> Color {
+        self.sample_spectrum(|wavelength| rgb_to_spectrum(c, wavelength))
+    }
+
+    /// Keeps only the hero wavelength, for paths that can't carry the others any further, such as
+    /// through a dispersive material.
+    pub fn keep_hero(&self, values: Color) -> Color {
+        Color::new(values.r * 3.0, 0.0, 0.0)
+    }
+
+    /// Converts values at each of the wavelengths to RGB through the CIE colour matching functions.
+    pub fn to_rgb(self, values: Color) -> Color {
+        (values.r * wavelength_color(self.0[0])
+            + values.g * wavelength_color(self.0[1])
+            + values.b * wavelength_color(self.0[2]))
+            / 3.0
+    }
+}
+
+/// Emission spectrum of a black body at a temperature in kelvin, scaled to average 1 over the visible
+/// range.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Blackbody {
+    temperature: f64,
+    scale: f64,
+}
+
+impl Blackbody {
+    pub fn new(temperature: f64) -> Blackbody {
+        let mut blackbody = Blackbody {
+            temperature,
+            scale: 1.0,
+        };
+        let steps = (WAVELENGTH_MAX - WAVELENGTH_MIN) as usize;
+        let sum: f64 = (0..steps)
+            .map(|i| blackbody.value(WAVELENGTH_MIN + i as f64 + 0.5))
+            .sum();
+        blackbody.scale = steps as f64 / sum;
+        blackbody
+    }
+
+    /// Planck's law at the given wavelength in nanometres, without its constant factor.
+    pub fn value(&self, wavelength: f64) -> f64 {
+        // second radiation constant hc/k, in nanometre kelvins
+        const C2: f64 = 1.4387769e7;
+        self.scale / (wavelength.powi(5) * ((C2 / (wavelength * self.temperature)).exp() - 1.0))
+    }
+
+    /// RGB colour of the light, for rendering without spectral mode.
+    pub fn color(&self) -> Color {
+        let steps = (WAVELENGTH_MAX - WAVELENGTH_MIN) as usize;
+        let sum = (0..steps)
+            .map(|i| WAVELENGTH_MIN + i as f64 + 0.5)
+            .map(|wavelength| self.value(wavelength) * wavelength_color(wavelength))
+            .fold(Color::black(), |acc, c| acc + c);
+        sum / steps as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::direction::Direction;
+    use crate::point::Point;
+    use crate::test_utils::*;
+
+    #[test]
+    pub fn wavelength_colors_average_to_white() {
+        let steps = 4000;
+        let sum = (0..steps)
+            .map(|i| wavelength_color(WAVELENGTH_MIN + (i as f64 + 0.5) * 0.1))
+            .fold(Color::black(), |acc, c| acc + c);
+        let mean = sum / steps as f64;
+        assert!((mean.r - 1.0).abs() < 1e-3 && (mean.g - 1.0).abs() < 1e-3 && (mean.b - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    pub fn wavelength_hues() {
+        let blue = wavelength_color(450.0);
+        assert!(blue.b > blue.g && blue.b > blue.r);
+        let green = wavelength_color(530.0);
+        assert!(green.g > green.r && green.g > green.b);
+        let red = wavelength_color(640.0);
+        assert!(red.r > red.g && red.r > red.b);
+        assert_approx_eq!(wavelength_color(WAVELENGTH_MAX + 100.0), Color::black());
+    }
+
+    #[test]
+    pub fn upsampled_white_is_flat() {
+        let w = Wavelengths::from_hero(400.0);
+        assert_approx_eq!(w.upsample(Color::new(0.5, 0.5, 0.5)), Color::new(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    pub fn upsampled_colors_keep_their_hue() {
+        let c = Color::new(0.9, 0.5, 0.1);
+        let spectrum = |wavelength: f64| rgb_to_spectrum(c, wavelength);
+        assert!(spectrum(650.0) > spectrum(530.0) && spectrum(530.0) > spectrum(450.0));
+        assert_approx_eq!(rgb_band_value(c, 650.0), 0.9);
+        assert_approx_eq!(rgb_band_value(c, 530.0), 0.5);
+        assert_approx_eq!(rgb_band_value(c, 450.0), 0.1);
+    }
+
+    #[test]
+    pub fn upsampled_colors_round_trip() {
+        let steps = 3000;
+        let range = WAVELENGTH_MAX - WAVELENGTH_MIN;
+        for c in [
+            Color::new(1.0, 0.0, 0.0),
+            Color::new(0.0, 1.0, 0.0),
+            Color::new(0.0, 0.0, 1.0),
+            Color::new(0.8, 0.2, 0.2),
+            Color::new(0.1, 0.6, 0.9),
+        ] {
+            // averaged over hero wavelengths spread evenly through the first third of the range
+            let sum = (0..steps)
+                .map(|i| {
+                    let w = Wavelengths::from_hero(WAVELENGTH_MIN + (i as f64 + 0.5) / steps as f64 * range / 3.0);
+                    w.to_rgb(w.upsample(c))
+                })
+                .fold(Color::black(), |acc, c| acc + c);
+            let mean = sum / steps as f64;
+            assert!(
+                (mean.r - c.r).abs() < 2e-3 && (mean.g - c.g).abs() < 2e-3 && (mean.b - c.b).abs() < 2e-3,
+                "{:?} came back as {:?}",
+                c,
+                mean
+            );
+        }
+    }
+
+    #[test]
+    pub fn path_wavelength_by_kind_of_path() {
+        let mut ray = Ray::primary(Point::zero(), Direction::new(0.0, 0.0, -1.0), 0);
+        assert_eq!((None, Color::white()), path_wavelength(false, &ray));
+        let (wavelength, tint) = path_wavelength(true, &ray);
+        assert_approx_eq!(tint, wavelength_color(wavelength.unwrap()));
+
+        ray.wavelengths = Some(Wavelengths::from_hero(500.0));
+        assert_eq!((Some(500.0), Color::white()), path_wavelength(true, &ray));
+        ray.wavelength = Some(600.0);
+        assert_eq!((Some(600.0), Color::white()), path_wavelength(true, &ray));
+    }
+
+    #[test]
+    pub fn wavelengths_spread_through_visible_range() {
+        let w = Wavelengths::from_hero(700.0);
+        assert_approx_eq!(w.0[1], 433.33333333333337);
+        assert_approx_eq!(w.0[2], 566.6666666666667);
+    }
+
+    #[test]
+    pub fn blackbody_colors() {
+        let warm = Blackbody::new(2700.0).color();
+        assert!(warm.r > warm.g && warm.g > warm.b);
+        let cool = Blackbody::new(10000.0).color();
+        assert!(cool.b > cool.r);
+    }
+}
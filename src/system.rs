@@ -1,18 +1,25 @@
 use std::cmp;
 use std::f64;
+use std::io;
+use std::io::{Read, Write};
+use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::Mutex;
 
 use rand::prelude::*;
 use rayon::prelude::*;
 
+use crate::binio;
 use crate::color::Color;
 use crate::direction::Direction;
+use crate::materials::ScatterKind;
 use crate::matrix::Matrix44f;
 use crate::object::Object;
 use crate::object::Transformation;
+use crate::passes::{Pass, PathSample};
 use crate::point::Point;
 use crate::sdl::Scene;
+use crate::spectrum::Wavelengths;
 use crate::vector::Vector2f;
 
 #[derive(Debug, Copy, Clone)]
@@ -23,49 +30,108 @@ pub struct Options {
     pub bias: f64,
     pub max_depth: u16,
     pub samples: u16,
+    pub passes: bool,
+    pub crop: Option<CropWindow>,
+    /// Animation frame the scene is evaluated at.
+    pub frame: u32,
+    /// Trace each path at a few wavelengths instead of in RGB.
+    pub spectral: bool,
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct Camera {
-    width: f64,
-    height: f64,
-    fov_factor: f64,
-    camera_to_world: Matrix44f,
-}
-
-impl Camera {
-    pub fn new(width: f64, height: f64, fov: f64, origin: Point, look_at: Point) -> Camera {
-        let up = Direction::new(0.0, 1.0, 0.0);
-        let zaxis = (origin - look_at).normalize();
-        let xaxis = up.normalize().cross(zaxis);
-        let yaxis = zaxis.cross(xaxis);
-        let camera_to_world = Matrix44f([
-            [xaxis.x, xaxis.y, xaxis.z, 0.0],
-            [yaxis.x, yaxis.y, yaxis.z, 0.0],
-            [zaxis.x, zaxis.y, zaxis.z, 0.0],
-            [origin.x, origin.y, origin.z, 1.0],
-        ]);
-
-        Camera {
-            width,
-            height,
-            fov_factor: (fov * 0.5).to_radians().tan(),
-            camera_to_world,
+impl Options {
+    /// The region of the image that is actually traced: the crop window if there is one, otherwise the
+    /// full frame.
+    pub fn render_window(&self) -> CropWindow {
+        self.crop.unwrap_or(CropWindow::new(0, 0, self.width, self.height))
+    }
+}
+
+/// A rectangular region of the image in pixel coordinates. The lower bounds are inclusive and the
+/// upper bounds exclusive.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CropWindow {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl CropWindow {
+    pub fn new(x0: u32, y0: u32, x1: u32, y1: u32) -> CropWindow {
+        CropWindow { x0, y0, x1, y1 }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+
+    /// Clips the window to an image of the given size, returning `None` if nothing is left.
+    pub fn clip(&self, width: u32, height: u32) -> Option<CropWindow> {
+        let clipped = CropWindow::new(self.x0, self.y0, self.x1.min(width), self.y1.min(height));
+        if clipped.x0 < clipped.x1 && clipped.y0 < clipped.y1 {
+            Some(clipped)
+        } else {
+            None
         }
     }
+}
+
+impl FromStr for CropWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CropWindow, String> {
+        let coords = s
+            .split(',')
+            .map(|c| {
+                c.trim()
+                    .parse::<u32>()
+                    .map_err(|e| format!("invalid crop coordinate '{}': {}", c, e))
+            })
+            .collect::<Result<Vec<u32>, String>>()?;
+        match coords[..] {
+            [x0, y0, x1, y1] if x0 < x1 && y0 < y1 => Ok(CropWindow::new(x0, y0, x1, y1)),
+            [_, _, _, _] => Err(String::from("crop window must satisfy x0 < x1 and y0 < y1")),
+            _ => Err(String::from("crop window must be given as x0,y0,x1,y1")),
+        }
+    }
+}
+
+/// An inclusive range of animation frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameRange {
+    pub start: u32,
+    pub end: u32,
+}
 
-    fn pixel_ray(&self, x: f64, y: f64) -> Ray {
-        let aspect_ratio = self.width / self.height;
-        let ndcx = x / self.width;
-        let ndcy = y / self.height;
-        let cx = (2.0 * ndcx - 1.0) * self.fov_factor * aspect_ratio;
-        let cy = (1.0 - 2.0 * ndcy) * self.fov_factor;
-        let origin = Point::zero() * self.camera_to_world;
-        let dir_point = Point::new(cx, cy, -1.0) * self.camera_to_world;
-        Ray::primary(origin, (dir_point - origin).normalize(), 0)
+impl FromStr for FrameRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FrameRange, String> {
+        let (start, end) = s
+            .split_once("..")
+            .ok_or_else(|| String::from("frame range must be given as start..end"))?;
+        let parse = |f: &str| {
+            f.trim()
+                .parse::<u32>()
+                .map_err(|e| format!("invalid frame '{}': {}", f, e))
+        };
+        let (start, end) = (parse(start)?, parse(end)?);
+        if start <= end {
+            Ok(FrameRange { start, end })
+        } else {
+            Err(String::from("frame range must satisfy start <= end"))
+        }
     }
 }
 
+/// Most steps a random walk through a subsurface scattering material may take before the path is
+/// given up as absorbed.
+const MAX_WALK_STEPS: u16 = 1024;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RayKind {
     Normal,
@@ -78,6 +144,14 @@ pub struct Ray {
     pub origin: Point,
     pub direction: Direction,
     pub depth: u16,
+    /// Moment within the shutter interval the ray was sent at, for motion blur.
+    pub time: f64,
+    /// Wavelength in nanometres the ray is limited to, once it has been split by dispersion.
+    pub wavelength: Option<f64>,
+    /// Wavelengths the path is traced at in spectral rendering.
+    pub wavelengths: Option<Wavelengths>,
+    /// Steps taken by the random walk the ray is part of inside a subsurface scattering material.
+    pub walk_steps: u16,
     pub inverse_direction: Direction,
     pub sign: [usize; 3],
 }
@@ -98,6 +172,10 @@ impl Ray {
             origin,
             direction,
             depth,
+            time: 0.0,
+            wavelength: None,
+            wavelengths: None,
+            walk_steps: 0,
             inverse_direction,
             sign: inverse_direction.sign(),
         }
@@ -109,13 +187,66 @@ impl Ray {
         object_ray
     }
 
-    pub fn cast(&self, context: &RenderContext) -> Color {
-        if self.depth >= context.options.max_depth {
-            context.scene.options.background_color
-        } else {
-            self.trace(&context.scene.objects, f64::MAX)
-                .map(|hit| self.hit_color(context, &hit))
-                .unwrap_or(context.scene.options.background_color)
+    pub fn cast(&self, context: &RenderContext, sample: &mut PathSample) {
+        let background = context.scene.options.background_color;
+        let mut ray = *self;
+        let mut throughput = Color::white();
+        let mut event = None;
+
+        // in spectral rendering, colours are values at each of the path's wavelengths until they
+        // reach the film
+        let wavelengths = self.wavelengths;
+        let to_spectrum = |c: Color| wavelengths.map_or(c, |w| w.upsample(c));
+        let to_film = |c: Color| wavelengths.map_or(c, |w| w.to_rgb(c));
+
+        loop {
+            let bounce = ray.depth - self.depth;
+            if ray.depth >= context.options.max_depth {
+                sample.add(event, bounce, None, to_film(throughput * to_spectrum(background)));
+                return;
+            }
+
+            let scattered = match ray.trace(&context.scene.objects, f64::MAX) {
+                Some(hit) => {
+                    let light_group = hit.object.light_group_index;
+                    let emitted = match &wavelengths {
+                        Some(w) => hit.object.material.emit_spectrum(context, &hit, w),
+                        None => hit.object.material.emit(context, &hit),
+                    };
+                    sample.add(event, bounce, light_group, to_film(throughput * emitted));
+                    hit.object.material.scatter(context, &hit)
+                }
+                None => None,
+            };
+
+            match scattered {
+                Some(s) => {
+                    throughput = throughput * to_spectrum(s.attenuation);
+                    if let (Some(w), None, Some(_)) = (&wavelengths, ray.wavelength, s.wavelength) {
+                        throughput = w.keep_hero(throughput);
+                    }
+                    event = event.or(Some(s.kind));
+                    // steps of a random walk have their own budget, as walks through dense media
+                    // can take many more of them than a path has bounces
+                    let (depth, walk_steps) = match s.kind {
+                        ScatterKind::Subsurface => (ray.depth, ray.walk_steps + 1),
+                        _ => (ray.depth + 1, 0),
+                    };
+                    if walk_steps > MAX_WALK_STEPS {
+                        return;
+                    }
+                    let time = ray.time;
+                    ray = Ray::primary(s.origin, s.direction, depth);
+                    ray.time = time;
+                    ray.walk_steps = walk_steps;
+                    ray.wavelength = s.wavelength;
+                    ray.wavelengths = wavelengths;
+                }
+                None => {
+                    sample.add(event, bounce, None, to_film(throughput * to_spectrum(background)));
+                    return;
+                }
+            }
         }
     }
 
@@ -131,15 +262,6 @@ impl Ray {
             .min_by(|(_, a), (_, b)| a.t.partial_cmp(&b.t).unwrap()) // find the nearest
             .map(|(o, i)| RayHit::new(self, o, i)) // create RayHit
     }
-
-    pub fn hit_color(&self, context: &RenderContext, hit: &RayHit) -> Color {
-        let e = hit.object.material.emit(context, hit);
-        let sr = hit.object.material.scatter(context, hit);
-        let s = sr.map(|s| s.attenuation * Ray::primary(s.origin, s.direction, self.depth + 1).cast(context));
-        let s = s.unwrap_or(context.scene.options.background_color);
-
-        e + s
-    }
 }
 
 impl Transformable for Ray {
@@ -193,7 +315,7 @@ impl Intersection {
         let tsign = self.t.signum();
         Intersection {
             t: tsign * (world_hit_point - world_ray.origin).length(),
-            n: (self.n * tx.object_to_world.inverse().transpose()).normalize(),
+            n: (self.n * tx.world_to_object.transpose()).normalize(),
             uv: self.uv,
         }
     }
@@ -220,10 +342,212 @@ pub struct RenderContext {
     pub recip_sqrt_spp: f64,
 }
 
+impl RenderContext {
+    pub fn new(options: Options, scene: Scene) -> RenderContext {
+        RenderContext {
+            options,
+            scene,
+            sqrt_spp: (options.samples as f64).sqrt() as u32,
+            recip_sqrt_spp: (options.samples as f64).sqrt().recip(),
+        }
+    }
+
+    /// The stratum coordinates of every sample to be rendered, one full image per stratum.
+    pub fn strata(&self) -> Vec<(u32, u32)> {
+        let sqrt_spp = self.sqrt_spp;
+        (0..sqrt_spp).flat_map(|i| (0..sqrt_spp).map(move |j| (i, j))).collect()
+    }
+}
+
 pub trait RenderProgress {
     fn render_started(&mut self, options: &Options);
-    fn sample_finished(&mut self, options: &Options, renderbuf: &Vec<Vec<Color>>);
-    fn render_finished(&mut self, options: &Options, renderbuf: &Vec<Vec<Color>>);
+    fn sample_finished(&mut self, options: &Options, renderbuf: &RenderBuffer);
+    fn render_finished(&mut self, options: &Options, renderbuf: &RenderBuffer);
+}
+
+/// A named image accumulated alongside the beauty pass.
+#[derive(Clone)]
+pub struct RenderLayer {
+    pub name: String,
+    pub buf: Vec<Vec<Color>>,
+}
+
+impl RenderLayer {
+    fn new(name: &str, width: u32, height: u32) -> RenderLayer {
+        RenderLayer {
+            name: String::from(name),
+            buf: alloc_render_buf(width, height),
+        }
+    }
+}
+
+/// Accumulated radiance for the beauty pass, the light path expression passes if enabled, and one
+/// layer per light group in the scene. The buffers cover `window` within a `width` x `height` frame and
+/// hold the sum of `samples` samples per pixel.
+#[derive(Clone)]
+pub struct RenderBuffer {
+    pub width: u32,
+    pub height: u32,
+    pub window: CropWindow,
+    pub samples: u32,
+    pub beauty: Vec<Vec<Color>>,
+    pub passes: Vec<RenderLayer>,
+    pub light_groups: Vec<RenderLayer>,
+}
+
+const RENDER_BUFFER_MAGIC: &[u8; 4] = b"RTRB";
+const RENDER_BUFFER_VERSION: u8 = 1;
+
+impl RenderBuffer {
+    pub fn new(options: &Options, scene: &Scene) -> RenderBuffer {
+        let window = options.render_window();
+        let (width, height) = (window.width(), window.height());
+        let passes = if options.passes {
+            Pass::all().to_vec()
+        } else {
+            Vec::new()
+        };
+        RenderBuffer {
+            width: options.width,
+            height: options.height,
+            window,
+            samples: 0,
+            beauty: alloc_render_buf(width, height),
+            passes: passes
+                .iter()
+                .map(|p| RenderLayer::new(p.name(), width, height))
+                .collect(),
+            light_groups: scene
+                .light_groups
+                .iter()
+                .map(|g| RenderLayer::new(&format!("light_{}", g), width, height))
+                .collect(),
+        }
+    }
+
+    pub fn layers(&self) -> impl Iterator<Item = &RenderLayer> {
+        self.passes.iter().chain(self.light_groups.iter())
+    }
+
+    fn bufs(&self) -> impl Iterator<Item = &Vec<Vec<Color>>> {
+        Some(&self.beauty).into_iter().chain(self.layers().map(|l| &l.buf))
+    }
+
+    fn bufs_mut(&mut self) -> impl Iterator<Item = &mut Vec<Vec<Color>>> {
+        Some(&mut self.beauty).into_iter().chain(
+            self.passes
+                .iter_mut()
+                .chain(self.light_groups.iter_mut())
+                .map(|l| &mut l.buf),
+        )
+    }
+
+    /// Whether the other buffer covers the same pixels with the same layers, so that the two can be
+    /// combined.
+    pub fn is_compatible(&self, other: &RenderBuffer) -> bool {
+        let names = |layers: &[RenderLayer]| layers.iter().map(|l| l.name.clone()).collect::<Vec<String>>();
+        self.width == other.width
+            && self.height == other.height
+            && self.window == other.window
+            && names(&self.passes) == names(&other.passes)
+            && names(&self.light_groups) == names(&other.light_groups)
+    }
+
+    /// Writes the raw linear radiance of every layer, in the order they were allocated, preceded by
+    /// the number of samples it holds.
+    pub fn write_pixels<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        binio::write_u32(w, self.samples)?;
+        for buf in self.bufs() {
+            for c in buf.iter().flat_map(|row| row.iter()) {
+                binio::write_f64(w, c.r)?;
+                binio::write_f64(w, c.g)?;
+                binio::write_f64(w, c.b)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads radiance written by `write_pixels` into a buffer with the same layout.
+    pub fn read_pixels<R: Read>(&mut self, r: &mut R) -> io::Result<()> {
+        self.samples = binio::read_u32(r)?;
+        for buf in self.bufs_mut() {
+            for c in buf.iter_mut().flat_map(|row| row.iter_mut()) {
+                c.r = binio::read_f64(r)?;
+                c.g = binio::read_f64(r)?;
+                c.b = binio::read_f64(r)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, x: usize, y: usize, sample: &PathSample) {
+        self.beauty[y][x] = sample.color;
+        for (layer, &c) in self.passes.iter_mut().zip(sample.passes.iter()) {
+            layer.buf[y][x] = c;
+        }
+        for (layer, &c) in self.light_groups.iter_mut().zip(sample.light_groups.iter()) {
+            layer.buf[y][x] = c;
+        }
+    }
+
+    /// Saves the buffer, including its frame, window and layer names, so that it can be merged with
+    /// renders of the same scene made elsewhere.
+    pub fn save<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(RENDER_BUFFER_MAGIC)?;
+        binio::write_u8(w, RENDER_BUFFER_VERSION)?;
+        binio::write_u32(w, self.width)?;
+        binio::write_u32(w, self.height)?;
+        binio::write_u32(w, self.window.x0)?;
+        binio::write_u32(w, self.window.y0)?;
+        binio::write_u32(w, self.window.x1)?;
+        binio::write_u32(w, self.window.y1)?;
+        for layers in [&self.passes, &self.light_groups] {
+            binio::write_u32(w, layers.len() as u32)?;
+            for layer in layers.iter() {
+                binio::write_str(w, &layer.name)?;
+            }
+        }
+        self.write_pixels(w)
+    }
+
+    pub fn load<R: Read>(r: &mut R) -> io::Result<RenderBuffer> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != RENDER_BUFFER_MAGIC || binio::read_u8(r)? != RENDER_BUFFER_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a render buffer file"));
+        }
+        let width = binio::read_u32(r)?;
+        let height = binio::read_u32(r)?;
+        let window = CropWindow::new(
+            binio::read_u32(r)?,
+            binio::read_u32(r)?,
+            binio::read_u32(r)?,
+            binio::read_u32(r)?,
+        );
+        if window.clip(width, height) != Some(window) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid render window"));
+        }
+        let read_layers = |r: &mut R| -> io::Result<Vec<RenderLayer>> {
+            let num_layers = binio::read_u32(r)?;
+            (0..num_layers)
+                .map(|_| Ok(RenderLayer::new(&binio::read_str(r)?, window.width(), window.height())))
+                .collect()
+        };
+        let passes = read_layers(r)?;
+        let light_groups = read_layers(r)?;
+
+        let mut buf = RenderBuffer {
+            width,
+            height,
+            window,
+            samples: 0,
+            beauty: alloc_render_buf(window.width(), window.height()),
+            passes,
+            light_groups,
+        };
+        buf.read_pixels(r)?;
+        Ok(buf)
+    }
 }
 
 fn alloc_render_buf(width: u32, height: u32) -> Vec<Vec<Color>> {
@@ -234,22 +558,33 @@ fn alloc_render_buf(width: u32, height: u32) -> Vec<Vec<Color>> {
     renderbuf
 }
 
-fn get_stratified_ray(context: &RenderContext, x: u32, y: u32, s_i: u32, s_j: u32) -> Ray {
+fn get_stratified_ray(context: &RenderContext, x: u32, y: u32, s_i: u32, s_j: u32) -> Option<Ray> {
     let mut rng = rand::rng();
     let s_x = ((s_i as f64 + rng.random::<f64>()) * context.recip_sqrt_spp) - 0.5;
     let s_y = ((s_j as f64 + rng.random::<f64>()) * context.recip_sqrt_spp) - 0.5;
     context.scene.camera.pixel_ray(x as f64 + s_x, y as f64 + s_y)
 }
 
-fn render_sample(context: &RenderContext, buf: &mut Vec<Vec<Color>>, s_i: u32, s_j: u32) {
-    buf.iter_mut().enumerate().for_each(|(y, row)| {
-        row.iter_mut().enumerate().for_each(|(x, pixel)| {
-            let x = x as u32;
-            let y = y as u32;
-            let ray = get_stratified_ray(context, x, y, s_i, s_j);
-            *pixel = ray.cast(&context);
-        });
-    });
+fn render_pixel(context: &RenderContext, sample: &mut PathSample, x: u32, y: u32, s_i: u32, s_j: u32) {
+    sample.reset();
+    if let Some(mut ray) = get_stratified_ray(context, x, y, s_i, s_j) {
+        if context.options.spectral {
+            ray.wavelengths = Some(Wavelengths::sample());
+        }
+        ray.cast(context, sample);
+        sample.scale(context.scene.camera.exposure);
+    }
+}
+
+fn render_sample(context: &RenderContext, buf: &mut RenderBuffer, s_i: u32, s_j: u32) {
+    let window = context.options.render_window();
+    let mut sample = PathSample::new(context.scene.light_groups.len());
+    for y in window.y0..window.y1 {
+        for x in window.x0..window.x1 {
+            render_pixel(context, &mut sample, x, y, s_i, s_j);
+            buf.set((x - window.x0) as usize, (y - window.y0) as usize, &sample);
+        }
+    }
 }
 
 fn combine_renderbuf(dest: &mut Vec<Vec<Color>>, src: &Vec<Vec<Color>>) {
@@ -260,6 +595,52 @@ fn combine_renderbuf(dest: &mut Vec<Vec<Color>>, src: &Vec<Vec<Color>>) {
     });
 }
 
+pub fn combine_render_buffers(dest: &mut RenderBuffer, src: &RenderBuffer) {
+    dest.samples += src.samples;
+    combine_renderbuf(&mut dest.beauty, &src.beauty);
+    for (dest_layer, src_layer) in dest.passes.iter_mut().zip(src.passes.iter()) {
+        combine_renderbuf(&mut dest_layer.buf, &src_layer.buf);
+    }
+    for (dest_layer, src_layer) in dest.light_groups.iter_mut().zip(src.light_groups.iter()) {
+        combine_renderbuf(&mut dest_layer.buf, &src_layer.buf);
+    }
+}
+
+/// Renders a single stratified sample of every pixel in the render window.
+pub fn render_stratum(context: &RenderContext, s_i: u32, s_j: u32) -> RenderBuffer {
+    let mut sample_buf = RenderBuffer::new(&context.options, &context.scene);
+    render_sample(context, &mut sample_buf, s_i, s_j);
+    sample_buf.samples = 1;
+    sample_buf
+}
+
+/// Like `render_stratum`, but renders the rows in parallel, for when there is only one stratum to
+/// render at a time.
+pub fn render_stratum_parallel(context: &RenderContext, s_i: u32, s_j: u32) -> RenderBuffer {
+    let window = context.options.render_window();
+    let rows: Vec<Vec<PathSample>> = (window.y0..window.y1)
+        .into_par_iter()
+        .map(|y| {
+            let mut sample = PathSample::new(context.scene.light_groups.len());
+            (window.x0..window.x1)
+                .map(|x| {
+                    render_pixel(context, &mut sample, x, y, s_i, s_j);
+                    sample.clone()
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut sample_buf = RenderBuffer::new(&context.options, &context.scene);
+    for (y, row) in rows.iter().enumerate() {
+        for (x, sample) in row.iter().enumerate() {
+            sample_buf.set(x, y, sample);
+        }
+    }
+    sample_buf.samples = 1;
+    sample_buf
+}
+
 pub fn render<T>(options: Options, scene: Scene, progress: &mut Arc<Mutex<T>>)
 where
     T: RenderProgress + Send,
@@ -269,30 +650,21 @@ where
         progress_guard.render_started(&options);
     }
 
-    let render_buf = Arc::new(Mutex::new(alloc_render_buf(options.width, options.height)));
-    let context = Arc::new(RenderContext {
-        options,
-        scene,
-        sqrt_spp: (options.samples as f64).sqrt() as u32,
-        recip_sqrt_spp: (options.samples as f64).sqrt().recip(),
-    });
+    let render_buf = Arc::new(Mutex::new(RenderBuffer::new(&options, &scene)));
+    let context = Arc::new(RenderContext::new(options, scene));
 
     {
         let render_buf = render_buf.clone();
         let progress = progress.clone();
 
-        let strat_coords: Vec<(u32, u32)> = (0..context.sqrt_spp)
-            .flat_map(|i| (0..context.sqrt_spp).map(move |j| (i, j)))
-            .collect();
+        let strat_coords = context.strata();
 
         strat_coords.into_par_iter().for_each(move |(s_i, s_j)| {
-            let mut sample_buf = alloc_render_buf(options.width, options.height);
-
-            render_sample(&context, &mut sample_buf, s_i, s_j);
+            let sample_buf = render_stratum(&context, s_i, s_j);
 
             {
                 let mut render_buf_guard = render_buf.lock().unwrap();
-                combine_renderbuf(&mut render_buf_guard, &sample_buf);
+                combine_render_buffers(&mut render_buf_guard, &sample_buf);
                 let mut progress_guard = progress.lock().unwrap();
                 progress_guard.sample_finished(&options, &render_buf_guard);
             }
@@ -305,3 +677,61 @@ where
         progress_guard.render_finished(&options, &render_buf_guard);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_crop_window() {
+        assert_eq!(Ok(CropWindow::new(10, 20, 30, 40)), "10,20,30,40".parse::<CropWindow>());
+        assert_eq!(Ok(CropWindow::new(0, 0, 5, 5)), " 0, 0, 5, 5 ".parse::<CropWindow>());
+        assert!("10,20,30".parse::<CropWindow>().is_err());
+        assert!("30,20,10,40".parse::<CropWindow>().is_err());
+        assert!("a,b,c,d".parse::<CropWindow>().is_err());
+    }
+
+    #[test]
+    fn parse_frame_range() {
+        assert_eq!(Ok(FrameRange { start: 1, end: 24 }), "1..24".parse::<FrameRange>());
+        assert_eq!(Ok(FrameRange { start: 5, end: 5 }), "5..5".parse::<FrameRange>());
+        assert!("24..1".parse::<FrameRange>().is_err());
+        assert!("1-24".parse::<FrameRange>().is_err());
+    }
+
+    #[test]
+    fn render_buffer_save_load() {
+        let mut buf = RenderBuffer {
+            width: 8,
+            height: 6,
+            window: CropWindow::new(2, 1, 5, 3),
+            samples: 4,
+            beauty: alloc_render_buf(3, 2),
+            passes: vec![RenderLayer::new("emission", 3, 2)],
+            light_groups: vec![RenderLayer::new("light_key", 3, 2)],
+        };
+        buf.beauty[1][2] = Color::new(0.25, 0.5, 0.75);
+        buf.light_groups[0].buf[0][1] = Color::new(1.0, 2.0, 3.0);
+
+        let mut bytes: Vec<u8> = Vec::new();
+        buf.save(&mut bytes).unwrap();
+        let loaded = RenderBuffer::load(&mut bytes.as_slice()).unwrap();
+
+        assert!(buf.is_compatible(&loaded));
+        assert_eq!(4, loaded.samples);
+        assert_eq!(Color::new(0.25, 0.5, 0.75), loaded.beauty[1][2]);
+        assert_eq!(Color::new(1.0, 2.0, 3.0), loaded.light_groups[0].buf[0][1]);
+
+        let mut merged = loaded.clone();
+        combine_render_buffers(&mut merged, &buf);
+        assert_eq!(8, merged.samples);
+        assert_eq!(Color::new(0.5, 1.0, 1.5), merged.beauty[1][2]);
+    }
+
+    #[test]
+    fn clip_crop_window() {
+        let w = CropWindow::new(100, 50, 300, 200);
+        assert_eq!(Some(CropWindow::new(100, 50, 200, 100)), w.clip(200, 100));
+        assert_eq!(None, w.clip(100, 100));
+    }
+}
@@ -1,11 +1,29 @@
 use std::fmt::Debug;
 
+use crate::color::Color;
 use crate::direction::Direction;
 use crate::matrix::Matrix44f;
 use crate::point::Point;
+use crate::system::Options;
 
 const TEST_EPSILON: f64 = 0.000001;
 
+/// Options for a tiny render, for tests that need to parse a scene or render a few samples.
+pub fn test_options() -> Options {
+    Options {
+        num_threads: 1,
+        width: 8,
+        height: 8,
+        bias: 1e-4,
+        max_depth: 4,
+        samples: 4,
+        passes: false,
+        crop: None,
+        frame: 0,
+        spectral: false,
+    }
+}
+
 pub trait ApproxEq: Debug {
     fn approx_eq(&self, other: &Self) -> bool;
 }
@@ -67,6 +85,15 @@ impl ApproxEq for Point {
     }
 }
 
+impl ApproxEq for Color {
+    fn approx_eq(&self, other: &Self) -> bool {
+        let a = [self.r, self.g, self.b];
+        let b = [other.r, other.g, other.b];
+
+        a.approx_eq(&b)
+    }
+}
+
 macro_rules! assert_approx_eq {
     ($a:expr, $b:expr) => {
         if !$a.approx_eq(&$b) {
@@ -51,6 +51,14 @@ impl PartialEq for Texture {
     }
 }
 
+impl Texture {
+    /// Average of the channels at the given point, for textures that drive a material parameter.
+    pub fn value_at_uv(&self, uv: Vector2f) -> f64 {
+        let c = self.color_at_uv(uv);
+        (c.r + c.g + c.b) / 3.0
+    }
+}
+
 impl ColorSource for Texture {
     fn color_at_uv(&self, uv: Vector2f) -> Color {
         match self {